@@ -28,9 +28,103 @@ OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 */
 
+use crate::value::ShiftKind;
 use std::cmp::Ordering;
 use std::fmt;
 
+/// The operands of an `Instruction`, as shared or mutable references depending on how it's
+/// borrowed
+macro_rules! instruction_operands {
+    ($instruction:expr) => {
+        match $instruction {
+            Instruction::Flip | Instruction::Ret | Instruction::Halt => vec![],
+            Instruction::Pop { dest } => dest.into_iter().collect(),
+            Instruction::Var { size, .. } => size.into_iter().collect(),
+            Instruction::Data { dest, values } => std::iter::once(dest).chain(values).collect(),
+            Instruction::DataFill { dest, count, value } => vec![dest, count, value],
+            Instruction::DataString { dest, text } => vec![dest, text],
+            Instruction::Define { value: op, .. }
+            | Instruction::Clear { target: op }
+            | Instruction::Inc { dest: op }
+            | Instruction::Dec { dest: op }
+            | Instruction::Push { src: op }
+            | Instruction::Call { target: op }
+            | Instruction::Not { op }
+            | Instruction::Neg { op }
+            | Instruction::Abs { op }
+            | Instruction::Fill { colour: op }
+            | Instruction::Print { src: op, .. }
+            | Instruction::Read { dest: op, .. }
+            | Instruction::Len { src: op }
+            | Instruction::Upper { src: op }
+            | Instruction::Lower { src: op }
+            | Instruction::Trim { src: op }
+            | Instruction::Reverse { src: op }
+            | Instruction::Ord { src: op }
+            | Instruction::Chr { src: op }
+            | Instruction::Type { src: op } => vec![op],
+            Instruction::Itoa { src, radix } | Instruction::Atoi { src, radix } => {
+                std::iter::once(src).chain(radix).collect()
+            }
+            Instruction::CharAt { src, index } => vec![src, index],
+            Instruction::Substr { src, start, length } => vec![src, start, length],
+            Instruction::Find { haystack, needle } => vec![haystack, needle],
+            Instruction::Split {
+                src,
+                separator,
+                dest,
+            } => vec![src, separator, dest],
+            Instruction::Set { value, dest } | Instruction::Store { value, dest } => {
+                vec![value, dest]
+            }
+            Instruction::Load { src, dest } | Instruction::Mov { src, dest } => vec![src, dest],
+            Instruction::Add { left, right }
+            | Instruction::Sub { left, right }
+            | Instruction::Mul { left, right }
+            | Instruction::Div { left, right }
+            | Instruction::FloorDiv { left, right }
+            | Instruction::Mod { left, right }
+            | Instruction::Min { left, right }
+            | Instruction::Max { left, right }
+            | Instruction::And { left, right }
+            | Instruction::Or { left, right }
+            | Instruction::Xor { left, right } => vec![left, right],
+            Instruction::Shift { value, amount, .. } => vec![value, amount],
+            Instruction::Jmp {
+                target,
+                comparison: None,
+            } => vec![target],
+            Instruction::Jmp {
+                target,
+                comparison: Some(Comparison { left, right, .. }),
+            } => vec![target, left, right],
+            Instruction::DivMod {
+                left,
+                right,
+                remainder,
+            } => vec![left, right, remainder],
+            Instruction::Pixel { x, y, colour } => vec![x, y, colour],
+            Instruction::Line {
+                x0,
+                y0,
+                x1,
+                y1,
+                colour,
+            } => vec![x0, y0, x1, y1, colour],
+            Instruction::Rect {
+                x,
+                y,
+                width,
+                height,
+                colour,
+            } => vec![x, y, width, height, colour],
+            Instruction::In { port, dest } => vec![port, dest],
+            Instruction::Out { src, port } => vec![src, port],
+            Instruction::Rand { dest, low, high } => vec![dest, low, high],
+        }
+    };
+}
+
 #[derive(Debug, Clone)]
 pub enum Statement {
     Label(String),
@@ -44,6 +138,27 @@ pub enum Instruction {
         name: String,
         value: Operand,
     },
+    /// Give a constant the address of `size` slots of memory, or one slot without a size
+    Var {
+        name: String,
+        size: Option<Operand>,
+    },
+    /// Put values in memory, one per slot, before the program starts
+    Data {
+        dest: Operand,
+        values: Vec<Operand>,
+    },
+    /// Put the same value in `count` slots of memory before the program starts
+    DataFill {
+        dest: Operand,
+        count: Operand,
+        value: Operand,
+    },
+    /// Put a string in memory, one character per slot, before the program starts
+    DataString {
+        dest: Operand,
+        text: Operand,
+    },
     Set {
         value: Operand,
         dest: Operand,
@@ -76,6 +191,33 @@ pub enum Instruction {
         left: Operand,
         right: Operand,
     },
+    FloorDiv {
+        left: Operand,
+        right: Operand,
+    },
+    Mod {
+        left: Operand,
+        right: Operand,
+    },
+    DivMod {
+        left: Operand,
+        right: Operand,
+        remainder: Operand,
+    },
+    Neg {
+        op: Operand,
+    },
+    Abs {
+        op: Operand,
+    },
+    Min {
+        left: Operand,
+        right: Operand,
+    },
+    Max {
+        left: Operand,
+        right: Operand,
+    },
     Inc {
         dest: Operand,
     },
@@ -117,11 +259,195 @@ pub enum Instruction {
     Not {
         op: Operand,
     },
+    Shift {
+        kind: ShiftKind,
+        value: Operand,
+        amount: Operand,
+    },
+
+    Pixel {
+        x: Operand,
+        y: Operand,
+        colour: Operand,
+    },
+    Fill {
+        colour: Operand,
+    },
+    Line {
+        x0: Operand,
+        y0: Operand,
+        x1: Operand,
+        y1: Operand,
+        colour: Operand,
+    },
+    Rect {
+        x: Operand,
+        y: Operand,
+        width: Operand,
+        height: Operand,
+        colour: Operand,
+    },
+    Flip,
+
+    In {
+        port: Operand,
+        dest: Operand,
+    },
+    Out {
+        src: Operand,
+        port: Operand,
+    },
+    Print {
+        src: Operand,
+        newline: bool,
+    },
+    Read {
+        dest: Operand,
+        line: bool,
+    },
+    Rand {
+        dest: Operand,
+        low: Operand,
+        high: Operand,
+    },
+
+    Len {
+        src: Operand,
+    },
+    CharAt {
+        src: Operand,
+        index: Operand,
+    },
+    Substr {
+        src: Operand,
+        start: Operand,
+        length: Operand,
+    },
+    Find {
+        haystack: Operand,
+        needle: Operand,
+    },
+    Upper {
+        src: Operand,
+    },
+    Lower {
+        src: Operand,
+    },
+    Trim {
+        src: Operand,
+    },
+    Reverse {
+        src: Operand,
+    },
+    Split {
+        src: Operand,
+        separator: Operand,
+        dest: Operand,
+    },
+
+    Itoa {
+        src: Operand,
+        radix: Option<Operand>,
+    },
+    Atoi {
+        src: Operand,
+        radix: Option<Operand>,
+    },
+    Ord {
+        src: Operand,
+    },
+    Chr {
+        src: Operand,
+    },
+    Type {
+        src: Operand,
+    },
 
     Ret,
     Halt,
 }
 
+impl Instruction {
+    /// The name of the instruction as it is written in a program
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Define { .. } => "DEFINE",
+            Instruction::Var { .. } => "VAR",
+            Instruction::Data { .. } => "DATA",
+            Instruction::DataString { .. } => "STRING",
+            Instruction::Set { .. } => "SET",
+            Instruction::Load { .. } => "LOAD",
+            Instruction::Store { .. } => "STORE",
+            Instruction::Clear { .. } => "CLEAR",
+            Instruction::Add { .. } => "ADD",
+            Instruction::Sub { .. } => "SUB",
+            Instruction::Mul { .. } => "MUL",
+            Instruction::Div { .. } => "DIV",
+            Instruction::FloorDiv { .. } => "FDIV",
+            Instruction::Mod { .. } => "MOD",
+            Instruction::DivMod { .. } => "DIVMOD",
+            Instruction::Neg { .. } => "NEG",
+            Instruction::Abs { .. } => "ABS",
+            Instruction::Min { .. } => "MIN",
+            Instruction::Max { .. } => "MAX",
+            Instruction::Inc { .. } => "INC",
+            Instruction::Dec { .. } => "DEC",
+            Instruction::Mov { .. } => "MOV",
+            Instruction::Push { .. } => "PUSH",
+            Instruction::Pop { .. } => "POP",
+            Instruction::Jmp { .. } => "JMP",
+            Instruction::Call { .. } => "CALL",
+            Instruction::And { .. } => "AND",
+            Instruction::Or { .. } => "OR",
+            Instruction::Xor { .. } => "XOR",
+            Instruction::Not { .. } => "NOT",
+            Instruction::Shift { kind, .. } => match kind {
+                ShiftKind::Left => "SHL",
+                ShiftKind::ArithmeticRight => "SHR",
+                ShiftKind::LogicalRight => "LSR",
+                ShiftKind::RotateLeft => "ROL",
+                ShiftKind::RotateRight => "ROR",
+            },
+            Instruction::Pixel { .. } => "PIXEL",
+            Instruction::Fill { .. } | Instruction::DataFill { .. } => "FILL",
+            Instruction::Line { .. } => "LINE",
+            Instruction::Rect { .. } => "RECT",
+            Instruction::Flip => "FLIP",
+            Instruction::In { .. } => "IN",
+            Instruction::Out { .. } => "OUT",
+            Instruction::Print { newline: false, .. } => "PRINT",
+            Instruction::Print { newline: true, .. } => "PRINTLN",
+            Instruction::Read { line: false, .. } => "READ",
+            Instruction::Read { line: true, .. } => "READLINE",
+            Instruction::Rand { .. } => "RAND",
+            Instruction::Len { .. } => "LEN",
+            Instruction::CharAt { .. } => "CHARAT",
+            Instruction::Substr { .. } => "SUBSTR",
+            Instruction::Find { .. } => "FIND",
+            Instruction::Upper { .. } => "UPPER",
+            Instruction::Lower { .. } => "LOWER",
+            Instruction::Trim { .. } => "TRIM",
+            Instruction::Reverse { .. } => "REVERSE",
+            Instruction::Split { .. } => "SPLIT",
+            Instruction::Itoa { .. } => "ITOA",
+            Instruction::Atoi { .. } => "ATOI",
+            Instruction::Ord { .. } => "ORD",
+            Instruction::Chr { .. } => "CHR",
+            Instruction::Type { .. } => "TYPE",
+            Instruction::Ret => "RET",
+            Instruction::Halt => "HALT",
+        }
+    }
+    /// Every operand of the instruction, including both sides of a jump's comparison
+    pub fn operands(&self) -> Vec<&Operand> {
+        instruction_operands!(self)
+    }
+    /// Every operand of the instruction, so they can be rewritten in place
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        instruction_operands!(self)
+    }
+}
+
 impl AsRef<Instruction> for Instruction {
     fn as_ref(&self) -> &Instruction {
         self
@@ -129,10 +455,27 @@ impl AsRef<Instruction> for Instruction {
 }
 
 impl fmt::Display for Instruction {
+    #[allow(clippy::too_many_lines)]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Instruction::Define { name, value } => write!(f, "DEFINE {name} {value}"),
-            Instruction::Set { value, dest } => write!(f, "SET {value}, {dest}"),
+            Instruction::Var { name, size: None } => write!(f, "VAR {name}"),
+            Instruction::Var {
+                name,
+                size: Some(size),
+            } => write!(f, "VAR {name}[{size}]"),
+            Instruction::Data { dest, values } => {
+                write!(f, "DATA {dest}")?;
+                for value in values {
+                    write!(f, ", {value}")?;
+                }
+                Ok(())
+            }
+            Instruction::DataFill { dest, count, value } => {
+                write!(f, "FILL {dest}, {count}, {value}")
+            }
+            Instruction::DataString { dest, text } => write!(f, "STRING {dest}, {text}"),
+            Instruction::Set { value, dest } => write!(f, "SET {dest}, {value}"),
             Instruction::Load { src, dest } => write!(f, "LOAD {src}, {dest}"),
             Instruction::Store { value, dest } => write!(f, "STORE {value}, {dest}"),
             Instruction::Clear { target } => write!(f, "CLEAR {target}"),
@@ -140,6 +483,17 @@ impl fmt::Display for Instruction {
             Instruction::Sub { left, right } => write!(f, "SUB {left}, {right}"),
             Instruction::Mul { left, right } => write!(f, "MUL {left}, {right}"),
             Instruction::Div { left, right } => write!(f, "DIV {left}, {right}"),
+            Instruction::FloorDiv { left, right } => write!(f, "FDIV {left}, {right}"),
+            Instruction::Mod { left, right } => write!(f, "MOD {left}, {right}"),
+            Instruction::DivMod {
+                left,
+                right,
+                remainder,
+            } => write!(f, "DIVMOD {left}, {right}, {remainder}"),
+            Instruction::Neg { op } => write!(f, "NEG {op}"),
+            Instruction::Abs { op } => write!(f, "ABS {op}"),
+            Instruction::Min { left, right } => write!(f, "MIN {left}, {right}"),
+            Instruction::Max { left, right } => write!(f, "MAX {left}, {right}"),
             Instruction::Inc { dest } => write!(f, "INC {dest}"),
             Instruction::Dec { dest } => write!(f, "DEC {dest}"),
             Instruction::Mov { src, dest } => write!(f, "MOV {src}, {dest}"),
@@ -163,6 +517,68 @@ impl fmt::Display for Instruction {
             Instruction::Or { left, right } => write!(f, "OR {left}, {right}"),
             Instruction::Xor { left, right } => write!(f, "XOR {left}, {right}"),
             Instruction::Not { op } => write!(f, "NOT {op}"),
+            Instruction::Shift { value, amount, .. } => {
+                write!(f, "{} {value}, {amount}", self.mnemonic())
+            }
+            Instruction::Pixel { x, y, colour } => write!(f, "PIXEL {x}, {y}, {colour}"),
+            Instruction::Fill { colour } => write!(f, "FILL {colour}"),
+            Instruction::Line {
+                x0,
+                y0,
+                x1,
+                y1,
+                colour,
+            } => write!(f, "LINE {x0}, {y0}, {x1}, {y1}, {colour}"),
+            Instruction::Rect {
+                x,
+                y,
+                width,
+                height,
+                colour,
+            } => write!(f, "RECT {x}, {y}, {width}, {height}, {colour}"),
+            Instruction::Flip => write!(f, "FLIP"),
+            Instruction::In { port, dest } => write!(f, "IN {port}, {dest}"),
+            Instruction::Out { src, port } => write!(f, "OUT {src}, {port}"),
+            Instruction::Print { src, newline } => {
+                if *newline {
+                    write!(f, "PRINTLN {src}")
+                } else {
+                    write!(f, "PRINT {src}")
+                }
+            }
+            Instruction::Read { dest, line } => {
+                if *line {
+                    write!(f, "READLINE {dest}")
+                } else {
+                    write!(f, "READ {dest}")
+                }
+            }
+            Instruction::Rand { dest, low, high } => write!(f, "RAND {dest}, {low}, {high}"),
+            Instruction::Len { src } => write!(f, "LEN {src}"),
+            Instruction::CharAt { src, index } => write!(f, "CHARAT {src}, {index}"),
+            Instruction::Substr { src, start, length } => {
+                write!(f, "SUBSTR {src}, {start}, {length}")
+            }
+            Instruction::Find { haystack, needle } => write!(f, "FIND {haystack}, {needle}"),
+            Instruction::Upper { src } => write!(f, "UPPER {src}"),
+            Instruction::Lower { src } => write!(f, "LOWER {src}"),
+            Instruction::Trim { src } => write!(f, "TRIM {src}"),
+            Instruction::Reverse { src } => write!(f, "REVERSE {src}"),
+            Instruction::Split {
+                src,
+                separator,
+                dest,
+            } => write!(f, "SPLIT {src}, {separator}, {dest}"),
+            Instruction::Itoa { src, radix } | Instruction::Atoi { src, radix } => {
+                write!(f, "{} {src}", self.mnemonic())?;
+                match radix {
+                    Some(radix) => write!(f, ", {radix}"),
+                    None => Ok(()),
+                }
+            }
+            Instruction::Ord { src } => write!(f, "ORD {src}"),
+            Instruction::Chr { src } => write!(f, "CHR {src}"),
+            Instruction::Type { src } => write!(f, "TYPE {src}"),
             Instruction::Ret => write!(f, "RET"),
             Instruction::Halt => write!(f, "HALT"),
         }
@@ -180,6 +596,10 @@ pub enum Operand {
     Constant(String),
     Character(String),
     String(String),
+    /// A constant expression in brackets, like `(.width * 2)`
+    Expression(Box<Expression>),
+    /// A memory address worked out from a constant expression, like `%(.base + 3)`
+    MemoryExpression(Box<Expression>),
 }
 
 impl fmt::Display for Operand {
@@ -190,9 +610,11 @@ impl fmt::Display for Operand {
             | Operand::IndirectMemory(s)
             | Operand::Number(s)
             | Operand::Identifier(s)
-            | Operand::Constant(s)
-            | Operand::Character(s)
-            | Operand::String(s) => write!(f, "{s}"),
+            | Operand::Constant(s) => write!(f, "{s}"),
+            Operand::Character(s) => write!(f, "'{}'", s.escape_debug()),
+            Operand::String(s) => write!(f, "\"{}\"", s.escape_debug()),
+            Operand::Expression(expression) => write!(f, "({expression})"),
+            Operand::MemoryExpression(expression) => write!(f, "%({expression})"),
         }
     }
 }
@@ -203,6 +625,70 @@ impl AsRef<Operand> for Operand {
     }
 }
 
+/// An expression made of numbers, strings, characters and constants, worked out when the program
+/// is compiled
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expression {
+    Operand(Operand),
+    Negate(Box<Expression>),
+    Binary {
+        op: ExpressionOp,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+}
+
+/// The arithmetic an `Expression` can do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExpressionOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl Expression {
+    /// Write the expression out, with brackets around it if it has an operator that could bind
+    /// less tightly than the one it's part of
+    fn fmt_nested(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Operand(_) | Expression::Negate(_) => write!(f, "{self}"),
+            Expression::Binary { .. } => write!(f, "({self})"),
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Operand(operand) => write!(f, "{operand}"),
+            Expression::Negate(expression) => {
+                write!(f, "-")?;
+                expression.fmt_nested(f)
+            }
+            Expression::Binary { op, left, right } => {
+                left.fmt_nested(f)?;
+                write!(f, " {op} ")?;
+                right.fmt_nested(f)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExpressionOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            ExpressionOp::Add => "+",
+            ExpressionOp::Sub => "-",
+            ExpressionOp::Mul => "*",
+            ExpressionOp::Div => "/",
+            ExpressionOp::Mod => "%",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Comparison {
     pub left: Operand,
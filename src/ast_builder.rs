@@ -30,11 +30,20 @@ OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 #![deny(clippy::pedantic)]
 
-use crate::ast::{Comparison, ComparisonOp, Instruction, Operand, Statement};
-use crate::ast_builder;
+use crate::ast::{
+    Comparison, ComparisonOp, Expression, ExpressionOp, Instruction, Operand, Statement,
+};
+use crate::error::{Expansion, Span, closest_match};
+use crate::macros::Macro;
+use crate::source::{self, SourceLoader};
+use crate::value::ShiftKind;
 use pest::Parser;
-use pest::iterators::Pair;
+use pest::error::LineColLocation;
+use pest::iterators::{Pair, Pairs};
+use pest::pratt_parser::{Assoc, Op, PrattParser};
 use pest_derive::Parser;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, LazyLock};
 
 #[derive(Parser)]
 #[grammar = "asm.pest"]
@@ -47,6 +56,13 @@ where
     operand_from_pair(inner.next().expect("Expected operand")).expect("")
 }
 
+fn optional_operand<'a, I>(inner: &mut I) -> Option<Operand>
+where
+    I: Iterator<Item = pest::iterators::Pair<'a, Rule>>,
+{
+    inner.next().map(|pair| operand_from_pair(pair).expect(""))
+}
+
 fn operand_from_pair(pair: Pair<Rule>) -> Result<Operand, String> {
     match pair.as_rule() {
         Rule::OPERAND => {
@@ -66,13 +82,115 @@ fn operand_from_pair(pair: Pair<Rule>) -> Result<Operand, String> {
             Ok(Operand::Register(parsed_name.to_string()))
         }
         Rule::NUMBER => Ok(Operand::Number(pair.as_str().to_string())),
-        Rule::IDENTIFIER => Ok(Operand::Identifier(pair.as_str().to_string())),
-        Rule::STRING => Ok(Operand::String(pair.as_str().to_string())),
-        Rule::CHARACTER => Ok(Operand::Character(pair.as_str().to_string())),
+        Rule::IDENTIFIER | Rule::LOCALLABEL | Rule::ANONYMOUSTARGET => {
+            Ok(Operand::Identifier(pair.as_str().to_string()))
+        }
+        Rule::STRING => unescape(pair.as_str()).map(Operand::String),
+        Rule::CHARACTER => character(pair.as_str()).map(Operand::Character),
         Rule::CONSTANT => Ok(Operand::Constant(pair.as_str().to_string())),
+        Rule::EXPRESSION => expression_from_pair(pair).map(|expression| match expression {
+            Expression::Operand(operand) => operand,
+            expression => Operand::Expression(Box::new(expression)),
+        }),
+        Rule::BRACKETED => {
+            let inner = pair.into_inner().next().ok_or("Expected an expression")?;
+            operand_from_pair(inner)
+        }
+        // `%.name+offset` is already shaped like an expression, so it's read as one
+        Rule::MEMORYEXPRESSION => {
+            let inner = pair.into_inner().next().ok_or("Expected an expression")?;
+            Ok(Operand::MemoryExpression(Box::new(expression_from_pair(
+                inner,
+            )?)))
+        }
         _ => Err(format!("Unknown operand type: {:?}", pair.as_rule())),
     }
 }
+
+/// How tightly the operators in an expression bind, loosest first
+static EXPRESSION_PARSER: LazyLock<PrattParser<Rule>> = LazyLock::new(|| {
+    PrattParser::new()
+        .op(Op::infix(Rule::PLUS, Assoc::Left) | Op::infix(Rule::MINUS, Assoc::Left))
+        .op(Op::infix(Rule::TIMES, Assoc::Left)
+            | Op::infix(Rule::DIVIDE, Assoc::Left)
+            | Op::infix(Rule::MODULO, Assoc::Left))
+        .op(Op::prefix(Rule::NEGATE))
+});
+
+fn expression_from_pair(pair: Pair<Rule>) -> Result<Expression, String> {
+    EXPRESSION_PARSER
+        .map_primary(|primary| match primary.as_rule() {
+            Rule::EXPRESSION => expression_from_pair(primary),
+            _ => operand_from_pair(primary).map(Expression::Operand),
+        })
+        .map_prefix(|_, expression| Ok(Expression::Negate(Box::new(expression?))))
+        .map_infix(|left, op, right| {
+            let op = match op.as_rule() {
+                Rule::PLUS => ExpressionOp::Add,
+                Rule::MINUS => ExpressionOp::Sub,
+                Rule::TIMES => ExpressionOp::Mul,
+                Rule::DIVIDE => ExpressionOp::Div,
+                Rule::MODULO => ExpressionOp::Mod,
+                rule => return Err(format!("Unknown operator: {rule:?}")),
+            };
+            Ok(Expression::Binary {
+                op,
+                left: Box::new(left?),
+                right: Box::new(right?),
+            })
+        })
+        .parse(pair.into_inner())
+}
+/// The text a quoted string or character literal stands for, with its escape sequences replaced
+/// # Errors
+/// Returns an error for an escape sequence that isn't `\n`, `\t`, `\r`, `\0`, `\"`, `\'`, `\\`
+/// or `\u{..}` with a valid code point in hex
+fn unescape(literal: &str) -> Result<String, String> {
+    let mut chars = literal.chars();
+    chars.next();
+    chars.next_back();
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some(c @ ('"' | '\'' | '\\')) => c,
+            Some('u') => {
+                let code: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                code.strip_prefix('{')
+                    .filter(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("\\u{code}}} is not a valid character"))?
+            }
+            Some(other) => return Err(format!("Unknown escape sequence \\{other}")),
+            None => return Err("Unfinished escape sequence".to_string()),
+        };
+        text.push(escaped);
+    }
+    Ok(text)
+}
+
+/// The text a character literal stands for
+/// # Errors
+/// Returns an error when the literal isn't exactly one character, or has an invalid escape
+fn character(literal: &str) -> Result<String, String> {
+    let text = unescape(literal)?;
+    if text.chars().count() == 1 {
+        Ok(text)
+    } else {
+        Err(format!(
+            "A character literal must be exactly one character, not {literal}"
+        ))
+    }
+}
+
 fn comparison_from_pair(pair: Pair<Rule>) -> Result<Comparison, String> {
     let mut inner = pair.into_inner();
     let left = operand_from_pair(inner.next().ok_or("Expected left for equality")?)?;
@@ -107,6 +225,29 @@ pub fn statement_from_pair(pair: &Pair<Rule>) -> Statement {
             let value = next_operand(&mut inner);
             Statement::CompileTime(Instruction::Define { name, value })
         }
+        Rule::VAR => {
+            let name = inner.next().unwrap().as_str().to_string();
+            let size = optional_operand(&mut inner);
+            Statement::CompileTime(Instruction::Var { name, size })
+        }
+        Rule::DATA => {
+            let dest = next_operand(&mut inner);
+            let values = inner
+                .map(|value| operand_from_pair(value).expect(""))
+                .collect();
+            Statement::CompileTime(Instruction::Data { dest, values })
+        }
+        Rule::FILLDATA => {
+            let dest = next_operand(&mut inner);
+            let count = next_operand(&mut inner);
+            let value = next_operand(&mut inner);
+            Statement::CompileTime(Instruction::DataFill { dest, count, value })
+        }
+        Rule::STRINGDATA => {
+            let dest = next_operand(&mut inner);
+            let text = next_operand(&mut inner);
+            Statement::CompileTime(Instruction::DataString { dest, text })
+        }
         Rule::SET => {
             let dest = next_operand(&mut inner);
             let value = next_operand(&mut inner);
@@ -163,6 +304,44 @@ pub fn statement_from_pair(pair: &Pair<Rule>) -> Statement {
             let right = next_operand(&mut inner);
             Statement::Instruction(Instruction::Div { left, right })
         }
+        Rule::FDIV => {
+            let left = next_operand(&mut inner);
+            let right = next_operand(&mut inner);
+            Statement::Instruction(Instruction::FloorDiv { left, right })
+        }
+        Rule::MOD => {
+            let left = next_operand(&mut inner);
+            let right = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Mod { left, right })
+        }
+        Rule::DIVMOD => {
+            let left = next_operand(&mut inner);
+            let right = next_operand(&mut inner);
+            let remainder = next_operand(&mut inner);
+            Statement::Instruction(Instruction::DivMod {
+                left,
+                right,
+                remainder,
+            })
+        }
+        Rule::NEG => {
+            let op = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Neg { op })
+        }
+        Rule::ABS => {
+            let op = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Abs { op })
+        }
+        Rule::MIN => {
+            let left = next_operand(&mut inner);
+            let right = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Min { left, right })
+        }
+        Rule::MAX => {
+            let left = next_operand(&mut inner);
+            let right = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Max { left, right })
+        }
         Rule::INC => {
             let dest = next_operand(&mut inner);
             Statement::Instruction(Instruction::Inc { dest })
@@ -186,6 +365,22 @@ pub fn statement_from_pair(pair: &Pair<Rule>) -> Statement {
             let right = next_operand(&mut inner);
             Statement::Instruction(Instruction::Xor { left, right })
         }
+        Rule::SHL | Rule::SHR | Rule::LSR | Rule::ROL | Rule::ROR => {
+            let kind = match pair.as_rule() {
+                Rule::SHL => ShiftKind::Left,
+                Rule::SHR => ShiftKind::ArithmeticRight,
+                Rule::LSR => ShiftKind::LogicalRight,
+                Rule::ROL => ShiftKind::RotateLeft,
+                _ => ShiftKind::RotateRight,
+            };
+            let value = next_operand(&mut inner);
+            let amount = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Shift {
+                kind,
+                value,
+                amount,
+            })
+        }
         Rule::NOT => {
             let op = next_operand(&mut inner);
             Statement::Instruction(Instruction::Not { op })
@@ -210,6 +405,131 @@ pub fn statement_from_pair(pair: &Pair<Rule>) -> Statement {
             let target = next_operand(&mut inner);
             Statement::Instruction(Instruction::Call { target })
         }
+        Rule::PIXEL => {
+            let x = next_operand(&mut inner);
+            let y = next_operand(&mut inner);
+            let colour = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Pixel { x, y, colour })
+        }
+        Rule::FILL => {
+            let colour = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Fill { colour })
+        }
+        Rule::DRAWLINE => {
+            let x0 = next_operand(&mut inner);
+            let y0 = next_operand(&mut inner);
+            let x1 = next_operand(&mut inner);
+            let y1 = next_operand(&mut inner);
+            let colour = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Line {
+                x0,
+                y0,
+                x1,
+                y1,
+                colour,
+            })
+        }
+        Rule::RECT => {
+            let x = next_operand(&mut inner);
+            let y = next_operand(&mut inner);
+            let width = next_operand(&mut inner);
+            let height = next_operand(&mut inner);
+            let colour = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Rect {
+                x,
+                y,
+                width,
+                height,
+                colour,
+            })
+        }
+        Rule::FLIP => Statement::Instruction(Instruction::Flip),
+        Rule::IN => {
+            let port = next_operand(&mut inner);
+            let dest = next_operand(&mut inner);
+            Statement::Instruction(Instruction::In { port, dest })
+        }
+        Rule::OUT => {
+            let src = next_operand(&mut inner);
+            let port = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Out { src, port })
+        }
+        Rule::PRINT | Rule::PRINTLN => {
+            let src = next_operand(&mut inner);
+            let newline = pair.as_rule() == Rule::PRINTLN;
+            Statement::Instruction(Instruction::Print { src, newline })
+        }
+        Rule::READ | Rule::READLINE => {
+            let dest = next_operand(&mut inner);
+            let line = pair.as_rule() == Rule::READLINE;
+            Statement::Instruction(Instruction::Read { dest, line })
+        }
+        Rule::RAND => {
+            let dest = next_operand(&mut inner);
+            let low = next_operand(&mut inner);
+            let high = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Rand { dest, low, high })
+        }
+        Rule::LEN => Statement::Instruction(Instruction::Len {
+            src: next_operand(&mut inner),
+        }),
+        Rule::CHARAT => {
+            let src = next_operand(&mut inner);
+            let index = next_operand(&mut inner);
+            Statement::Instruction(Instruction::CharAt { src, index })
+        }
+        Rule::SUBSTR => {
+            let src = next_operand(&mut inner);
+            let start = next_operand(&mut inner);
+            let length = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Substr { src, start, length })
+        }
+        Rule::FIND => {
+            let haystack = next_operand(&mut inner);
+            let needle = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Find { haystack, needle })
+        }
+        Rule::UPPER => Statement::Instruction(Instruction::Upper {
+            src: next_operand(&mut inner),
+        }),
+        Rule::LOWER => Statement::Instruction(Instruction::Lower {
+            src: next_operand(&mut inner),
+        }),
+        Rule::TRIM => Statement::Instruction(Instruction::Trim {
+            src: next_operand(&mut inner),
+        }),
+        Rule::REVERSE => Statement::Instruction(Instruction::Reverse {
+            src: next_operand(&mut inner),
+        }),
+        Rule::SPLIT => {
+            let src = next_operand(&mut inner);
+            let separator = next_operand(&mut inner);
+            let dest = next_operand(&mut inner);
+            Statement::Instruction(Instruction::Split {
+                src,
+                separator,
+                dest,
+            })
+        }
+        Rule::ITOA => {
+            let src = next_operand(&mut inner);
+            let radix = optional_operand(&mut inner);
+            Statement::Instruction(Instruction::Itoa { src, radix })
+        }
+        Rule::ATOI => {
+            let src = next_operand(&mut inner);
+            let radix = optional_operand(&mut inner);
+            Statement::Instruction(Instruction::Atoi { src, radix })
+        }
+        Rule::ORD => Statement::Instruction(Instruction::Ord {
+            src: next_operand(&mut inner),
+        }),
+        Rule::CHR => Statement::Instruction(Instruction::Chr {
+            src: next_operand(&mut inner),
+        }),
+        Rule::TYPE => Statement::Instruction(Instruction::Type {
+            src: next_operand(&mut inner),
+        }),
         Rule::RET => Statement::Instruction(Instruction::Ret),
         Rule::HALT => Statement::Instruction(Instruction::Halt),
         Rule::STORE => {
@@ -221,22 +541,327 @@ pub fn statement_from_pair(pair: &Pair<Rule>) -> Statement {
     }
 }
 
-pub fn parse_program<T: AsRef<str>>(
-    contents: T,
-) -> Result<Vec<Statement>, Box<pest::error::Error<ast_builder::Rule>>> {
-    let mut statements: Vec<Statement> = Vec::new();
-    let parse_results = ASMParser::parse(Rule::program, contents.as_ref());
-    match parse_results {
-        Ok(pairs) => {
-            for pair in pairs {
-                if pair.as_rule() == Rule::EOI {
-                    continue;
+/// Make sure every string and character literal in a statement can be unescaped, so that
+/// building the statement can't fail on one
+/// # Errors
+/// Returns an error pointing at the first literal that is invalid
+fn check_literals(pair: &Pair<Rule>) -> Result<(), Box<pest::error::Error<Rule>>> {
+    for literal in pair.clone().into_inner().flatten() {
+        let checked = match literal.as_rule() {
+            Rule::STRING => unescape(literal.as_str()),
+            Rule::CHARACTER => character(literal.as_str()),
+            _ => continue,
+        };
+        if let Err(message) = checked {
+            return Err(Box::new(pest::error::Error::new_from_span(
+                pest::error::ErrorVariant::CustomError { message },
+                literal.as_span(),
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// How deeply macros can call each other before it's assumed they'll never stop
+const MAX_MACRO_DEPTH: usize = 64;
+
+type ParseError = Box<pest::error::Error<Rule>>;
+
+/// Parse a whole program that may be in the file `file`, expanding every macro it calls and
+/// including every file it includes with `loader`
+/// # Errors
+/// Returns an error pointing at the first part of the program that can't be parsed, the first
+/// macro that can't be defined or called, or the first file that can't be included
+pub fn parse_program(
+    contents: &str,
+    file: Option<&str>,
+    loader: Option<&dyn SourceLoader>,
+) -> Result<Vec<(Statement, Span)>, ParseError> {
+    let in_file = |error: pest::error::Error<Rule>| match file {
+        Some(file) => Box::new(error.with_path(file)),
+        None => Box::new(error),
+    };
+    let pairs = ASMParser::parse(Rule::program, contents).map_err(in_file)?;
+    let file: Option<Arc<str>> = file.map(Arc::from);
+    let mut builder = ProgramBuilder {
+        loader,
+        macros: HashMap::new(),
+        expansions: 0,
+        statements: Vec::new(),
+        file: file.clone(),
+        including: file.iter().cloned().collect(),
+        included: file.into_iter().collect(),
+    };
+    builder.add_file(pairs).map_err(|error| in_file(*error))?;
+    Ok(builder.statements)
+}
+
+fn custom_error(message: String, pair: &Pair<Rule>) -> ParseError {
+    Box::new(pest::error::Error::new_from_span(
+        pest::error::ErrorVariant::CustomError { message },
+        pair.as_span(),
+    ))
+}
+
+/// Where a pest error is, as a line and column
+fn error_position(error: &pest::error::Error<Rule>) -> (usize, usize) {
+    match error.line_col {
+        LineColLocation::Pos(position) | LineColLocation::Span(position, _) => position,
+    }
+}
+
+/// The macro expansion some source is part of, and the macro it came from
+type Context<'a> = Option<(&'a Macro, &'a Arc<Expansion>)>;
+
+/// Builds up the statements of a program, expanding macros and including files as it goes
+struct ProgramBuilder<'a> {
+    /// Where included files are loaded from
+    loader: Option<&'a dyn SourceLoader>,
+    macros: HashMap<String, Macro>,
+    /// How many macro calls have been expanded so far, which makes each expansion's labels and
+    /// constants unique
+    expansions: usize,
+    statements: Vec<(Statement, Span)>,
+    /// The file being added, if it has a name
+    file: Option<Arc<str>>,
+    /// The files that are part way through being included, outermost first
+    including: Vec<Arc<str>>,
+    /// Every file that has been included, which are never included again
+    included: HashSet<Arc<str>>,
+}
+
+impl ProgramBuilder<'_> {
+    /// Add the statements in a whole file to the program, after collecting the macros it defines
+    /// so they can be called before they're defined
+    /// # Errors
+    /// Returns an error when a macro is defined twice, or the statements can't be added
+    fn add_file(&mut self, pairs: Pairs<Rule>) -> Result<(), ParseError> {
+        self.collect_macros(pairs.clone())?;
+        self.add(pairs, None, 0)
+    }
+    /// Add every macro defined in some parsed source
+    /// # Errors
+    /// Returns an error when a macro is defined twice
+    fn collect_macros(&mut self, pairs: Pairs<Rule>) -> Result<(), ParseError> {
+        for pair in pairs.filter(|pair| pair.as_rule() == Rule::MACRO) {
+            let (line, column) = pair.line_col();
+            let mut name = String::new();
+            let mut params = Vec::new();
+            let mut body = String::new();
+            let mut body_line = line + 1;
+            for part in pair.clone().into_inner() {
+                match part.as_rule() {
+                    Rule::IDENTIFIER => part.as_str().clone_into(&mut name),
+                    Rule::PARAMETERS => {
+                        params = part
+                            .into_inner()
+                            .map(|param| param.as_str().to_string())
+                            .collect();
+                    }
+                    Rule::MACROBODY => {
+                        body_line = part.line_col().0;
+                        part.as_str().clone_into(&mut body);
+                    }
+                    _ => {}
                 }
-                let statement = statement_from_pair(&pair);
-                statements.push(statement);
             }
+            if ASMParser::parse(Rule::MNEMONIC, &name).is_ok() {
+                return Err(custom_error(
+                    format!("The macro {name} has the same name as an instruction"),
+                    &pair,
+                ));
+            }
+            if let Some(existing) = self.macros.get(&name) {
+                return Err(custom_error(
+                    format!("The macro {name} is already defined on {}", existing.span),
+                    &pair,
+                ));
+            }
+            self.macros.insert(
+                name.clone(),
+                Macro {
+                    name,
+                    params,
+                    body,
+                    span: Span {
+                        file: self.file.clone(),
+                        ..Span::new(line, column)
+                    },
+                    body_line,
+                },
+            );
+        }
+        Ok(())
+    }
+    /// Add the statements in some parsed source to the program
+    /// # Errors
+    /// Returns an error when a literal is invalid, a macro can't be expanded or a file can't be
+    /// included
+    fn add(
+        &mut self,
+        pairs: Pairs<Rule>,
+        context: Context,
+        depth: usize,
+    ) -> Result<(), ParseError> {
+        for pair in pairs {
+            match pair.as_rule() {
+                Rule::MACRO if context.is_some() => {
+                    return Err(custom_error(
+                        "A macro can't be defined inside another macro".to_string(),
+                        &pair,
+                    ));
+                }
+                Rule::INCLUDE if context.is_some() => {
+                    return Err(custom_error(
+                        "A file can't be included inside a macro".to_string(),
+                        &pair,
+                    ));
+                }
+                // Macros were all collected before anything was added
+                Rule::EOI | Rule::MACRO => {}
+                Rule::INCLUDE => self.include(&pair)?,
+                Rule::MACROCALL => self.expand(&pair, context, depth)?,
+                _ => {
+                    check_literals(&pair)?;
+                    let statement = statement_from_pair(&pair);
+                    self.statements
+                        .push((statement, self.span_of(&pair, context)));
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Add the file an `INCLUDE` names to the program, unless it has already been included
+    /// # Errors
+    /// Returns an error when the file includes itself, can't be loaded, or can't be parsed
+    fn include(&mut self, include: &Pair<Rule>) -> Result<(), ParseError> {
+        check_literals(include)?;
+        let path = include
+            .clone()
+            .into_inner()
+            .next()
+            .map(|path| unescape(path.as_str()))
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        let name: Arc<str> = Arc::from(source::resolve(self.file.as_deref(), &path));
+        if self.including.contains(&name) {
+            let chain: Vec<&str> = self
+                .including
+                .iter()
+                .skip_while(|file| **file != name)
+                .map(AsRef::as_ref)
+                .chain([name.as_ref()])
+                .collect();
+            return Err(custom_error(
+                format!("{name} can't include itself: {}", chain.join(" -> ")),
+                include,
+            ));
+        }
+        if self.included.contains(&name) {
+            return Ok(());
+        }
+        let Some(loader) = self.loader else {
+            return Err(custom_error(
+                format!("Can't include {name}, because there is nowhere to load files from"),
+                include,
+            ));
+        };
+        let contents = loader
+            .load(&name)
+            .map_err(|error| custom_error(format!("Can't include {name}: {error}"), include))?;
+        let in_file = |error: pest::error::Error<Rule>| {
+            let (line, column) = error_position(&error);
+            custom_error(
+                format!(
+                    "In {name} on line {line}, column {column}: {}",
+                    error.variant.message()
+                ),
+                include,
+            )
+        };
+        let pairs = ASMParser::parse(Rule::program, &contents).map_err(in_file)?;
+        self.included.insert(Arc::clone(&name));
+        self.including.push(Arc::clone(&name));
+        let outer = self.file.replace(Arc::clone(&name));
+        let added = self.add_file(pairs);
+        self.file = outer;
+        self.including.pop();
+        added.map_err(|error| in_file(*error))
+    }
+    /// Add the body of the macro a `MACROCALL` calls to the program
+    /// # Errors
+    /// Returns an error when the macro doesn't exist, is given the wrong number of arguments,
+    /// calls macros too deeply, or its body can't be parsed once the arguments are in it
+    fn expand(
+        &mut self,
+        call: &Pair<Rule>,
+        context: Context,
+        depth: usize,
+    ) -> Result<(), ParseError> {
+        let mut parts = call.clone().into_inner();
+        let name = parts.next().map(|name| name.as_str()).unwrap_or_default();
+        let args: Vec<&str> = parts.map(|arg| arg.as_str().trim()).collect();
+        let Some(definition) = self.macros.get(name).cloned() else {
+            let message = match closest_match(name, self.macros.keys().map(String::as_str)) {
+                Some(suggestion) => format!(
+                    "There is no instruction or macro called {name}. Did you mean {suggestion}?"
+                ),
+                None => format!("There is no instruction or macro called {name}"),
+            };
+            return Err(custom_error(message, call));
+        };
+        if args.len() != definition.params.len() {
+            return Err(custom_error(
+                format!(
+                    "The macro {name} takes {} arguments, but was given {}",
+                    definition.params.len(),
+                    args.len()
+                ),
+                call,
+            ));
+        }
+        if depth >= MAX_MACRO_DEPTH {
+            return Err(custom_error(
+                format!("Macros are nested more than {MAX_MACRO_DEPTH} deep, starting with {name}"),
+                call,
+            ));
+        }
+        self.expansions += 1;
+        let source = definition.expand(&args, self.expansions);
+        let in_body = |error: pest::error::Error<Rule>| {
+            let (line, column) = error_position(&error);
+            let position = Span {
+                file: definition.span.file.clone(),
+                ..Span::new(definition.body_line + line - 1, column)
+            };
+            custom_error(
+                format!("In macro {name} on {position}: {}", error.variant.message()),
+                call,
+            )
+        };
+        let pairs = ASMParser::parse(Rule::program, &source).map_err(in_body)?;
+        let expansion = Arc::new(Expansion {
+            name: definition.name.clone(),
+            call: self.span_of(call, context),
+        });
+        self.add(pairs, Some((&definition, &expansion)), depth + 1)
+            .map_err(|error| in_body(*error))
+    }
+    /// Where a pair is in the program, following it back into the body of a macro if it came
+    /// from one
+    fn span_of(&self, pair: &Pair<Rule>, context: Context) -> Span {
+        let (line, column) = pair.line_col();
+        match context {
+            Some((definition, expansion)) => Span {
+                file: definition.span.file.clone(),
+                line: definition.body_line + line - 1,
+                column,
+                expansion: Some(Arc::clone(expansion)),
+            },
+            None => Span {
+                file: self.file.clone(),
+                ..Span::new(line, column)
+            },
         }
-        Err(e) => return Err(Box::new(e)),
     }
-    Ok(statements)
 }
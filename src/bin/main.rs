@@ -29,10 +29,28 @@ OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 */
 
 #![deny(clippy::pedantic)]
-use asm_interpreter::Interpreter;
+use asm_interpreter::display::{frame_path, render_ansi, save_display};
+use asm_interpreter::level::Level;
+use asm_interpreter::{FileSystemLoader, Interpreter};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
 
-fn main() {
-    let source = r#"
+const USAGE: &str =
+    "Usage: asm_interpreter [--level FILE [--seed SEED]...] [--dump-display FILE] [--show-display] [--every-frame] [PROGRAM]
+
+  PROGRAM               The program to run. Runs a built in example when left out
+  --level FILE          Check the program against every test case in a level file instead of
+                        running it
+  --seed SEED           Only run the random test case with this seed, along with the fixed test
+                        cases. Can be given more than once
+  --dump-display FILE   Save the display to FILE when the program ends. FILE can be .ppm, or .png
+                        when built with the png feature
+  --show-display        Print the display to the terminal when the program ends
+  --every-frame         Also dump or show the display every time the program signals a frame with
+                        FLIP. Dumped frames are numbered, so frame.ppm becomes frame-0001.ppm";
+
+const EXAMPLE: &str = r#"
 DEFINE .name "my_name"
 define .age 100 
 
@@ -41,11 +59,7 @@ START:
     LOAD %0xFF, R1
     CLEAR R4
     SET A, 1
-    SET %0, 1
-    set %1, 2
-    SET %2, 3
-    SET %3, 4
-    SET %4, 5
+    DATA %0, 1, 2, 3, 4, 5
 
 MAIN:
     SUB 100, .age
@@ -87,13 +101,143 @@ IS_FINE:
     STORE A, %17
     AND 2,6
 "#;
+
+#[derive(Default)]
+struct Options {
+    program: Option<PathBuf>,
+    level: Option<PathBuf>,
+    seeds: Vec<u64>,
+    dump_display: Option<PathBuf>,
+    show_display: bool,
+    every_frame: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dump-display" => {
+                let path = args.next().ok_or("--dump-display needs a file name")?;
+                options.dump_display = Some(PathBuf::from(path));
+            }
+            "--level" => {
+                let path = args.next().ok_or("--level needs a file name")?;
+                options.level = Some(PathBuf::from(path));
+            }
+            "--seed" => {
+                let seed = args.next().ok_or("--seed needs a number")?;
+                let seed = seed
+                    .parse()
+                    .map_err(|e| format!("Invalid seed '{seed}': {e}"))?;
+                options.seeds.push(seed);
+            }
+            "--show-display" => options.show_display = true,
+            "--every-frame" => options.every_frame = true,
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{arg}'")),
+            _ if options.program.is_none() => options.program = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument '{arg}'")),
+        }
+    }
+    Ok(options)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{e}");
+            }
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(path) = &options.level {
+        return verify_level(path, options.program.as_deref(), &options.seeds);
+    }
+
     let mut interpreter = Interpreter::new();
-    let result = interpreter.parse(source);
+    if options.every_frame {
+        let dump_display = options.dump_display.clone();
+        let show_display = options.show_display;
+        interpreter.on_frame(move |frame, display| {
+            if let Some(path) = &dump_display {
+                let path = frame_path(path, frame);
+                if let Err(e) = save_display(display, &path) {
+                    eprintln!("Failed to save frame to '{}': {e}", path.display());
+                }
+            }
+            if show_display {
+                println!("Frame {frame}:\n{}", render_ansi(display));
+            }
+        });
+    }
+    interpreter.set_source_loader(FileSystemLoader);
+    let result = match &options.program {
+        Some(path) => interpreter.parse_file(&path.to_string_lossy()),
+        None => interpreter.parse(EXAMPLE),
+    };
 
     match result {
         Ok(()) => {
             interpreter.run();
         }
-        Err(e) => eprintln!("Failed to parse: {e:?}"),
+        Err(e) => {
+            eprintln!("Failed to parse: {e:?}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let Ok(display) = interpreter.display.read() else {
+        eprintln!("Display lock poisoned");
+        return ExitCode::FAILURE;
+    };
+    if let Some(path) = &options.dump_display
+        && let Err(e) = save_display(&display, path)
+    {
+        eprintln!("Failed to save display to '{}': {e}", path.display());
+        return ExitCode::FAILURE;
+    }
+    if options.show_display {
+        print!("{}", render_ansi(&display));
+    }
+    ExitCode::SUCCESS
+}
+
+fn verify_level(path: &Path, program: Option<&Path>, seeds: &[u64]) -> ExitCode {
+    let level = match Level::load(path) {
+        Ok(level) => level,
+        Err(e) => {
+            eprintln!("Failed to load '{}': {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = match (program, seeds.is_empty()) {
+        (Some(program), true) => {
+            level.verify_file(Arc::new(FileSystemLoader), &program.to_string_lossy())
+        }
+        (Some(program), false) => level.verify_file_with_seeds(
+            Arc::new(FileSystemLoader),
+            &program.to_string_lossy(),
+            seeds,
+        ),
+        (None, true) => level.verify(EXAMPLE),
+        (None, false) => level.verify_with_seeds(EXAMPLE, seeds),
+    };
+    match report {
+        Ok(report) => {
+            println!("{report}");
+            if report.passed() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to verify: {e}");
+            ExitCode::FAILURE
+        }
     }
 }
@@ -0,0 +1,71 @@
+/*BSD 3-Clause License
+
+Copyright (c) 2025, Jeffrey Smith
+
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+
+1. Redistributions of source code must retain the above copyright notice, this
+   list of conditions and the following disclaimer.
+
+2. Redistributions in binary form must reproduce the above copyright notice,
+   this list of conditions and the following disclaimer in the documentation
+   and/or other materials provided with the distribution.
+
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+
+THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+*/
+
+use std::io::{self, Write};
+use std::sync::{Arc, RwLock};
+
+/// An output sink for `PRINT` and `PRINTLN` that can be read back, for tests and for showing
+/// program output in the game. Clones share the same buffer
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer(Arc<RwLock<Vec<u8>>>);
+
+impl SharedBuffer {
+    #[must_use]
+    pub fn new() -> SharedBuffer {
+        SharedBuffer::default()
+    }
+    /// Everything written so far. Invalid UTF-8 is replaced
+    /// # Panics
+    /// Panics if the lock is poisoned
+    #[must_use]
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.read().expect("Buffer lock poisoned")).into_owned()
+    }
+    /// Throw away everything written so far
+    /// # Panics
+    /// Panics if the lock is poisoned
+    pub fn clear(&self) {
+        self.0.write().expect("Buffer lock poisoned").clear();
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .write()
+            .map_err(|e| io::Error::other(format!("{e}")))?
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+/*BSD 3-Clause License
+
+Copyright (c) 2025, Jeffrey Smith
+
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+
+1. Redistributions of source code must retain the above copyright notice, this
+   list of conditions and the following disclaimer.
+
+2. Redistributions in binary form must reproduce the above copyright notice,
+   this list of conditions and the following disclaimer in the documentation
+   and/or other materials provided with the distribution.
+
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+
+THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+*/
+
+use crate::Value;
+use crate::error::InterpreterError;
+use crate::memory_map::MemoryRegion;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Width of the display in pixels
+pub const DISPLAY_WIDTH: usize = 16;
+/// Height of the display in pixels
+pub const DISPLAY_HEIGHT: usize = 16;
+/// Number of pixels on the display
+pub const DISPLAY_PIXELS: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+
+/// Split a packed `0xRRGGBB` colour into its channels. Returns `None` if the colour is negative or
+/// too large to be packed
+#[must_use]
+pub fn unpack_colour(colour: i64) -> Option<(i32, i32, i32)> {
+    if !(0..=0xFF_FFFF).contains(&colour) {
+        return None;
+    }
+    let [.., r, g, b] = colour.to_be_bytes();
+    Some((i32::from(r), i32::from(g), i32::from(b)))
+}
+
+/// Pack a pixel into a `0xRRGGBB` number. Channels are masked to 0..=255
+#[must_use]
+pub fn pack_colour((r, g, b): (i32, i32, i32)) -> i64 {
+    (i64::from(r & 0xFF) << 16) | (i64::from(g & 0xFF) << 8) | i64::from(b & 0xFF)
+}
+
+/// Index into the display buffer, or `None` if the coordinates are off the display
+pub(crate) fn pixel_index(x: i64, y: i64) -> Option<usize> {
+    match (usize::try_from(x), usize::try_from(y)) {
+        (Ok(x), Ok(y)) if x < DISPLAY_WIDTH && y < DISPLAY_HEIGHT => Some(y * DISPLAY_WIDTH + x),
+        _ => None,
+    }
+}
+
+pub(crate) fn on_display(x: i64, y: i64) -> bool {
+    pixel_index(x, y).is_some()
+}
+
+/// Set a pixel if it is on the display. Returns whether anything was drawn
+pub(crate) fn plot(
+    display: &mut [(i32, i32, i32)],
+    x: i64,
+    y: i64,
+    colour: (i32, i32, i32),
+) -> bool {
+    if let Some(index) = pixel_index(x, y) {
+        display[index] = colour;
+        true
+    } else {
+        false
+    }
+}
+
+/// Write the display out as a binary PPM image
+/// # Errors
+/// Returns an error if writing fails
+pub fn write_ppm<W: Write>(display: &[(i32, i32, i32)], writer: &mut W) -> io::Result<()> {
+    write!(writer, "P6\n{DISPLAY_WIDTH} {DISPLAY_HEIGHT}\n255\n")?;
+    writer.write_all(&rgb_bytes(display))
+}
+
+/// Write the display out as a PNG image
+/// # Errors
+/// Returns an error if encoding or writing fails
+#[cfg(feature = "png")]
+pub fn write_png<W: Write>(display: &[(i32, i32, i32)], writer: &mut W) -> io::Result<()> {
+    let width = u32::try_from(DISPLAY_WIDTH).map_err(io::Error::other)?;
+    let height = u32::try_from(DISPLAY_HEIGHT).map_err(io::Error::other)?;
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut png_writer = encoder.write_header().map_err(io::Error::other)?;
+    png_writer
+        .write_image_data(&rgb_bytes(display))
+        .map_err(io::Error::other)?;
+    png_writer.finish().map_err(io::Error::other)
+}
+
+/// Save the display to a file. Files ending in `.png` are saved as PNG, which needs the `png`
+/// feature, and everything else is saved as PPM
+/// # Errors
+/// Returns an error if the file can't be written
+pub fn save_display(display: &[(i32, i32, i32)], path: &Path) -> io::Result<()> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+    {
+        return save_png(display, path);
+    }
+    let mut file = BufWriter::new(File::create(path)?);
+    write_ppm(display, &mut file)?;
+    file.flush()
+}
+
+#[cfg(feature = "png")]
+fn save_png(display: &[(i32, i32, i32)], path: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_png(display, &mut file)?;
+    file.flush()
+}
+
+#[cfg(not(feature = "png"))]
+fn save_png(_display: &[(i32, i32, i32)], path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "Can't save '{}', PNG support needs the `png` feature",
+            path.display()
+        ),
+    ))
+}
+
+/// The path to save a numbered frame to, so `frame.ppm` becomes `frame-0003.ppm` for frame 3
+#[must_use]
+pub fn frame_path(path: &Path, frame: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}-{frame:04}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{frame:04}"),
+    };
+    path.with_file_name(name)
+}
+
+/// Render the display for a terminal using ANSI true colour escapes. Every pixel is two
+/// characters wide so that it comes out roughly square
+#[must_use]
+pub fn render_ansi(display: &[(i32, i32, i32)]) -> String {
+    let mut rendered = String::new();
+    for row in display.chunks(DISPLAY_WIDTH) {
+        for &(r, g, b) in row {
+            let _ = write!(
+                rendered,
+                "\x1b[48;2;{};{};{}m  ",
+                channel(r),
+                channel(g),
+                channel(b)
+            );
+        }
+        rendered.push_str("\x1b[0m\n");
+    }
+    rendered
+}
+
+fn rgb_bytes(display: &[(i32, i32, i32)]) -> Vec<u8> {
+    display
+        .iter()
+        .flat_map(|&(r, g, b)| [channel(r), channel(g), channel(b)])
+        .collect()
+}
+
+fn channel(value: i32) -> u8 {
+    u8::try_from(value.clamp(0, 255)).unwrap_or(u8::MAX)
+}
+
+/// Maps the display into the address space, one address per pixel going left to right, top to
+/// bottom. Pixels are read and written as packed `0xRRGGBB` numbers
+pub struct DisplayRegion {
+    display: Arc<RwLock<Vec<(i32, i32, i32)>>>,
+}
+
+impl DisplayRegion {
+    #[must_use]
+    pub fn new(display: Arc<RwLock<Vec<(i32, i32, i32)>>>) -> DisplayRegion {
+        DisplayRegion { display }
+    }
+}
+
+impl MemoryRegion for DisplayRegion {
+    fn size(&self) -> usize {
+        DISPLAY_PIXELS
+    }
+
+    fn read(&self, offset: usize) -> Result<Value, InterpreterError> {
+        let display = self
+            .display
+            .read()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
+        Ok(Value::Number(pack_colour(display[offset])))
+    }
+
+    fn write(&self, offset: usize, value: Value) -> Result<(), InterpreterError> {
+        let colour = match value {
+            Value::Number(n) => unpack_colour(n).ok_or(InterpreterError::InvalidColour(n))?,
+            Value::String(_) => {
+                return Err(InterpreterError::TypeMismatch(Box::new((
+                    value,
+                    Value::Number(0),
+                ))));
+            }
+        };
+        let mut display = self
+            .display
+            .write()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
+        display[offset] = colour;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_display() -> Vec<(i32, i32, i32)> {
+        let mut display = vec![(0, 0, 0); DISPLAY_PIXELS];
+        display[0] = (255, 0, 0);
+        display[DISPLAY_PIXELS - 1] = (1, 2, 300);
+        display
+    }
+
+    #[test]
+    fn test_write_ppm() {
+        let mut buffer = Vec::new();
+        write_ppm(&test_display(), &mut buffer).unwrap();
+
+        let header = b"P6\n16 16\n255\n";
+        assert_eq!(&buffer[..header.len()], header);
+        assert_eq!(buffer.len(), header.len() + DISPLAY_PIXELS * 3);
+        assert_eq!(&buffer[header.len()..header.len() + 3], &[255, 0, 0]);
+        assert_eq!(&buffer[buffer.len() - 3..], &[1, 2, 255]);
+    }
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_write_png() {
+        let mut buffer = Vec::new();
+        write_png(&test_display(), &mut buffer).unwrap();
+        assert_eq!(&buffer[..8], b"\x89PNG\r\n\x1a\n");
+    }
+    #[test]
+    fn test_render_ansi() {
+        let rendered = render_ansi(&test_display());
+        assert_eq!(rendered.lines().count(), DISPLAY_HEIGHT);
+        assert!(rendered.starts_with("\x1b[48;2;255;0;0m  \x1b[48;2;0;0;0m  "));
+        assert!(rendered.ends_with("\x1b[48;2;1;2;255m  \x1b[0m\n"));
+    }
+    #[test]
+    fn test_frame_path() {
+        assert_eq!(
+            frame_path(Path::new("out/frame.ppm"), 3),
+            PathBuf::from("out/frame-0003.ppm")
+        );
+        assert_eq!(
+            frame_path(Path::new("frame"), 12),
+            PathBuf::from("frame-0012")
+        );
+    }
+}
@@ -28,16 +28,154 @@ OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 */
 
+use std::fmt;
+use std::sync::Arc;
 use thiserror::Error;
 
 use crate::ast_builder;
 use crate::value::Value;
 
+/// Where something is in the source of a program
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    /// The file this is in, when it isn't the program's own unnamed source
+    pub file: Option<Arc<str>>,
+    /// The line, starting at 1
+    pub line: usize,
+    /// The column, starting at 1
+    pub column: usize,
+    /// The macro call this came from, when it's part of a macro's body
+    pub expansion: Option<Arc<Expansion>>,
+}
+
+/// A call to a macro, which its body was expanded in place of
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Expansion {
+    /// The name of the macro
+    pub name: String,
+    /// Where the macro was called
+    pub call: Span,
+}
+
+impl Span {
+    #[must_use]
+    pub fn new(line: usize, column: usize) -> Self {
+        Span {
+            file: None,
+            line,
+            column,
+            expansion: None,
+        }
+    }
+    /// Where the line and column are, with the file first if there is one
+    fn fmt_position(&self, f: &mut fmt::Formatter<'_>, line: &str) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}, line ")?;
+        } else {
+            write!(f, "{line} ")?;
+        }
+        write!(f, "{}, column {}", self.line, self.column)
+    }
+    /// Say which macro call this came from, if any
+    fn fmt_expansion(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.expansion {
+            Some(expansion) => write!(
+                f,
+                " in macro {}, called on {}",
+                expansion.name, expansion.call
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_position(f, "line")?;
+        self.fmt_expansion(f)
+    }
+}
+
+/// A problem with a program found before it runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.span.fmt_position(f, "Line")?;
+        self.span.fmt_expansion(f)?;
+        write!(f, ": {}", self.message)
+    }
+}
+
+fn join_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The candidate closest to `name`, if one is close enough that `name` is probably a typo of it.
+/// Case is ignored, and ties go to the candidate that sorts first
+pub(crate) fn closest_match<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| {
+            let distance = edit_distance(&name.to_lowercase(), &candidate.to_lowercase());
+            (distance, candidate)
+        })
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// How many characters have to be inserted, removed, replaced or swapped with their neighbour to
+/// turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
 #[derive(Debug, Error)]
 pub enum InterpreterError {
     #[error("Failed to parse program: {0}")]
     ParseError(#[from] Box<pest::error::Error<ast_builder::Rule>>),
 
+    #[error("Failed to load {0}: {1}")]
+    Load(String, std::io::Error),
+
+    #[error("Failed to compile program:\n{}", join_diagnostics(.0))]
+    Compile(Vec<Diagnostic>),
+
     #[error("Invalid operand: {0}")]
     InvalidOperand(String),
 
@@ -50,6 +188,9 @@ pub enum InterpreterError {
     #[error("Division by zero in {0}/{1}")]
     DivisionByZero(i64, i64),
 
+    #[error("Overflow: {0}")]
+    Overflow(String),
+
     #[error("Operation not supported on these types: {0:?}")]
     TypeMismatch(Box<(Value, Value)>),
 
@@ -65,6 +206,21 @@ pub enum InterpreterError {
     #[error("Cannot set an identifier: {0}")]
     CannotSetIdentifier(String),
 
+    #[error("Invalid colour: {0}, expected 0xRRGGBB with every channel in 0..=255")]
+    InvalidColour(i64),
+
+    #[error("Pixel ({0}, {1}) is outside of the display")]
+    InvalidPixel(i64, i64),
+
+    #[error("Invalid port: {0}")]
+    InvalidPort(String),
+
+    #[error("Nothing left to read")]
+    EndOfInput,
+
+    #[error("Console I/O failed: {0}")]
+    Io(#[from] std::io::Error),
+
     #[error("Memory lock poisoned: {0}")]
     LockPoisoned(String),
 
@@ -83,6 +239,21 @@ pub enum ValueError {
     #[error("Conversion error: {0}")]
     ConversionError(String),
 
+    #[error("Overflow: {0}")]
+    Overflow(String),
+
     #[error("Invalid operation: {0}")]
     InvalidOperation(String),
 }
+
+#[derive(Debug, Error)]
+pub enum LevelError {
+    #[error("Failed to read level: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid level: {0}")]
+    Parse(#[from] toml::de::Error),
+
+    #[error("Invalid level: {0}")]
+    Invalid(String),
+}
@@ -28,17 +28,44 @@ OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 */
 
-use crate::ast::{Comparison, Instruction, Operand, Statement};
-use crate::error::{InterpreterError, ValueError};
+use crate::ast::{Comparison, Expression, ExpressionOp, Instruction, Operand, Statement};
+use crate::display::{
+    DISPLAY_HEIGHT, DISPLAY_PIXELS, DISPLAY_WIDTH, DisplayRegion, on_display, plot, unpack_colour,
+};
+use crate::error::{Diagnostic, Expansion, InterpreterError, Span, ValueError, closest_match};
+use crate::memory_map::{MemoryMap, MemoryRegion};
+use crate::restrictions::Restrictions;
+use crate::rng::Rng;
+use crate::snapshot::Snapshot;
+use crate::source::{self, SourceLoader};
+use crate::value::{Arithmetic, ArithmeticMode, ShiftKind};
 use crate::{Value, ast_builder};
+use std::collections::{HashSet, VecDeque};
 use std::convert::TryInto;
 use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Write};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::{collections::HashMap, num::ParseIntError, sync::Arc, sync::RwLock};
 
 const RAM_SLOTS: usize = 256;
-const DISPLAY_PIXELS: usize = 16 * 16;
+/// The address of the top left pixel of the display, which is mapped directly above RAM
+pub const DISPLAY_ADDRESS: usize = RAM_SLOTS;
+/// How many input ports and output ports there are for `IN` and `OUT`
+pub const PORTS: usize = 4;
 const ACC: &str = "a";
+const FLAGS: &str = "f";
+/// The bit of the F register that holds the carry
+pub const FLAG_CARRY: i64 = 1;
+/// The bit of the F register that is set when arithmetic overflows
+pub const FLAG_OVERFLOW: i64 = 2;
+
+/// An arithmetic operation on two `Value`, like `Value::div_with`
+type BinaryArithmetic = fn(&Value, &Value, Arithmetic) -> Result<(Value, bool), ValueError>;
+/// An arithmetic operation on one `Value`, like `Value::neg_with`
+type UnaryArithmetic = fn(&Value, Arithmetic) -> Result<(Value, bool), ValueError>;
+
+/// Called with the frame number and the display every time the program signals a frame
+type FrameCallback = Box<dyn FnMut(usize, &[(i32, i32, i32)]) + Send>;
 
 const REGISTERS: [&str; 10] = ["a", "f", "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7"];
 
@@ -50,19 +77,57 @@ pub struct Interpreter {
     /// A `Vec` that contains all the potential memory addresses accessible for the vm. Do not push
     /// new values into this
     pub memory: Arc<RwLock<Vec<Value>>>,
+    /// Regions of the address space above RAM, like the display
+    memory_map: MemoryMap,
     /// The stack accessible to the vm. This can have as many values as requested pushed to it
     pub stack: Arc<RwLock<Vec<Value>>>,
+    /// Queues of values for the program to read with `IN`, one per port. These are filled by the
+    /// host
+    pub inputs: Arc<RwLock<Vec<VecDeque<Value>>>>,
+    /// Queues of values the program has written with `OUT`, one per port
+    pub outputs: Arc<RwLock<Vec<VecDeque<Value>>>>,
     /// A list of labels used for `Call` and `Jmp`
     labels: HashMap<String, usize>,
     /// A list of constants to pull from
     constants: HashMap<String, Value>,
     statements: Vec<Statement>,
+    /// Where each statement starts in the source
+    spans: Vec<Span>,
+    /// The instructions and registers the program may use
+    restrictions: Restrictions,
     /// The program counter
     pub pc: AtomicUsize,
     /// The full call stack. Needs to be accessible sot that debugging is possible
     pub call_stack: Arc<RwLock<Vec<usize>>>,
     /// A way to check if the machine is running
     pub running: AtomicBool,
+    /// How many instructions have been executed
+    pub cycles: AtomicUsize,
+    /// The most values that have been on the stack at once
+    pub peak_stack_depth: AtomicUsize,
+    /// Every RAM address the program has written to
+    memory_written: RwLock<HashSet<usize>>,
+    /// Set while the program is blocked on `IN` from an empty port. It carries on from the same
+    /// instruction once there is something to read
+    pub waiting: AtomicBool,
+    /// How many frames the program has signalled with `FLIP`
+    pub frames: AtomicUsize,
+    /// Set by `FLIP` when a frame is ready to be shown. The host should clear this once it has
+    /// drawn the frame
+    pub frame_ready: AtomicBool,
+    on_frame: Option<FrameCallback>,
+    /// Where `RAND` gets its numbers from
+    rng: Rng,
+    /// How many bits wide a number is for arithmetic and the shift and rotate instructions
+    word_width: u32,
+    /// What arithmetic does when a result doesn't fit in a word
+    arithmetic_mode: ArithmeticMode,
+    /// Where `PRINT` and `PRINTLN` write to
+    output: Box<dyn Write + Send>,
+    /// Where `READ` and `READLINE` read from
+    input: Box<dyn BufRead + Send>,
+    /// Where files the program includes with `INCLUDE` are loaded from
+    source_loader: Option<Box<dyn SourceLoader>>,
 }
 
 impl Default for Interpreter {
@@ -79,46 +144,500 @@ impl Interpreter {
             registers.insert((*reg).to_string(), Value::default());
         }
 
+        let display = Arc::new(RwLock::new(vec![(0, 0, 0); DISPLAY_PIXELS]));
+        let memory_map = MemoryMap::with_region(
+            DISPLAY_ADDRESS,
+            Box::new(DisplayRegion::new(Arc::clone(&display))),
+        );
+
         Interpreter {
-            display: Arc::new(RwLock::new(vec![(0, 0, 0); DISPLAY_PIXELS])),
+            display,
             registers: Arc::new(RwLock::new(registers)),
             memory: Arc::new(RwLock::new(vec![Value::Number(0); RAM_SLOTS])),
+            memory_map,
             stack: Arc::new(RwLock::new(Vec::new())),
+            inputs: Arc::new(RwLock::new(vec![VecDeque::new(); PORTS])),
+            outputs: Arc::new(RwLock::new(vec![VecDeque::new(); PORTS])),
             labels: HashMap::new(),
             constants: HashMap::new(),
             statements: Vec::new(),
+            spans: Vec::new(),
+            restrictions: Restrictions::new(),
             pc: AtomicUsize::new(0),
             call_stack: Arc::new(RwLock::new(Vec::new())),
             running: AtomicBool::new(true),
+            cycles: AtomicUsize::new(0),
+            peak_stack_depth: AtomicUsize::new(0),
+            memory_written: RwLock::new(HashSet::new()),
+            waiting: AtomicBool::new(false),
+            frames: AtomicUsize::new(0),
+            frame_ready: AtomicBool::new(false),
+            on_frame: None,
+            rng: Rng::new(0),
+            word_width: 64,
+            arithmetic_mode: ArithmeticMode::Checked,
+            output: Box::new(io::stdout()),
+            input: Box::new(BufReader::new(io::stdin())),
+            source_loader: None,
+        }
+    }
+    /// Map a region into the address space, starting at `start`
+    /// # Errors
+    /// Returns an error if the region would overlap RAM or a region that is already mapped, like
+    /// the display
+    pub fn map_memory<R: MemoryRegion + 'static>(
+        &mut self,
+        start: usize,
+        region: R,
+    ) -> Result<(), InterpreterError> {
+        if start < RAM_SLOTS {
+            return Err(InterpreterError::InvalidMemoryAddress(format!(
+                "Region at '{start}' overlaps RAM"
+            )));
+        }
+        self.memory_map.map(start, Box::new(region))
+    }
+    /// Queue up a value for the program to read from `port` with `IN`
+    /// # Errors
+    /// Returns an error if the port doesn't exist
+    pub fn push_input(&self, port: usize, value: Value) -> Result<(), InterpreterError> {
+        self.inputs
+            .write()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?
+            .get_mut(port)
+            .ok_or(InterpreterError::InvalidPort(port.to_string()))?
+            .push_back(value);
+        Ok(())
+    }
+    /// Take everything the program has written to `port` with `OUT` so far
+    /// # Errors
+    /// Returns an error if the port doesn't exist
+    pub fn take_output(&self, port: usize) -> Result<Vec<Value>, InterpreterError> {
+        let mut outputs = self
+            .outputs
+            .write()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
+        let output = outputs
+            .get_mut(port)
+            .ok_or(InterpreterError::InvalidPort(port.to_string()))?;
+        Ok(output.drain(..).collect())
+    }
+    /// Send everything written with `PRINT` and `PRINTLN` to `output` instead of stdout
+    pub fn set_output<W: Write + Send + 'static>(&mut self, output: W) {
+        self.output = Box::new(output);
+    }
+    /// Read input for `READ` and `READLINE` from `input` instead of stdin
+    pub fn set_input<R: BufRead + Send + 'static>(&mut self, input: R) {
+        self.input = Box::new(input);
+    }
+    /// Load files the program includes with `INCLUDE` from `loader`. Without one, a program can't
+    /// include anything
+    pub fn set_source_loader<L: SourceLoader + 'static>(&mut self, loader: L) {
+        self.source_loader = Some(Box::new(loader));
+    }
+    /// Call `callback` with the frame number, starting at 1, and the contents of the display every
+    /// time the program signals a frame with `FLIP`
+    pub fn on_frame<F>(&mut self, callback: F)
+    where
+        F: FnMut(usize, &[(i32, i32, i32)]) + Send + 'static,
+    {
+        self.on_frame = Some(Box::new(callback));
+    }
+    /// Only let programs use the instructions and registers `restrictions` allows. Set this
+    /// before calling `parse`
+    pub fn restrict(&mut self, restrictions: Restrictions) {
+        self.restrictions = restrictions;
+    }
+    /// Seed the numbers `RAND` gives. The same seed always gives the same numbers, and the seed
+    /// is 0 until this is called
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+    /// Set how many bits wide numbers are for arithmetic and the shift and rotate instructions.
+    /// This is 64 until it is changed. Use 8 or 16 to feel like a 6502
+    /// # Errors
+    /// Returns an error if `bits` isn't from 1 to 64
+    pub fn set_word_width(&mut self, bits: u32) -> Result<(), InterpreterError> {
+        if !(1..=64).contains(&bits) {
+            return Err(InterpreterError::Other(format!(
+                "Invalid word width {bits}, it must be from 1 to 64"
+            )));
+        }
+        self.word_width = bits;
+        Ok(())
+    }
+    /// Set what `ADD`, `SUB`, `MUL`, `DIV`, `FDIV`, `INC`, `DEC`, `NEG` and `ABS` do when a
+    /// result doesn't fit in a word. This is `ArithmeticMode::Checked` until it is changed, which
+    /// makes overflowing an error. In the other modes the overflow flag in F is set instead
+    pub fn set_arithmetic_mode(&mut self, mode: ArithmeticMode) {
+        self.arithmetic_mode = mode;
+    }
+    fn arithmetic(&self) -> Arithmetic {
+        Arithmetic {
+            mode: self.arithmetic_mode,
+            width: self.word_width,
         }
     }
+    /// Save the state of the machine, so it can be put back with `restore`. This doesn't include
+    /// the program, the console, or memory regions mapped in with `map_memory`
+    /// # Errors
+    /// Returns an error if a lock is poisoned
+    pub fn snapshot(&self) -> Result<Snapshot, InterpreterError> {
+        Ok(Snapshot {
+            registers: self.registers.read().map_err(poisoned)?.clone(),
+            memory: self.memory.read().map_err(poisoned)?.clone(),
+            display: self.display.read().map_err(poisoned)?.clone(),
+            stack: self.stack.read().map_err(poisoned)?.clone(),
+            call_stack: self.call_stack.read().map_err(poisoned)?.clone(),
+            inputs: self.inputs.read().map_err(poisoned)?.clone(),
+            outputs: self.outputs.read().map_err(poisoned)?.clone(),
+            pc: self.pc.load(Ordering::SeqCst),
+            running: self.running.load(Ordering::SeqCst),
+            waiting: self.waiting.load(Ordering::SeqCst),
+            cycles: self.cycles.load(Ordering::SeqCst),
+            peak_stack_depth: self.peak_stack_depth.load(Ordering::SeqCst),
+            memory_written: self.memory_written.read().map_err(poisoned)?.clone(),
+            frames: self.frames.load(Ordering::SeqCst),
+            frame_ready: self.frame_ready.load(Ordering::SeqCst),
+            rng: self.rng.clone(),
+        })
+    }
+    /// Put the machine back the way it was when `snapshot` was taken
+    /// # Errors
+    /// Returns an error if a lock is poisoned
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), InterpreterError> {
+        self.registers
+            .write()
+            .map_err(poisoned)?
+            .clone_from(&snapshot.registers);
+        self.memory
+            .write()
+            .map_err(poisoned)?
+            .clone_from(&snapshot.memory);
+        self.display
+            .write()
+            .map_err(poisoned)?
+            .clone_from(&snapshot.display);
+        self.stack
+            .write()
+            .map_err(poisoned)?
+            .clone_from(&snapshot.stack);
+        self.call_stack
+            .write()
+            .map_err(poisoned)?
+            .clone_from(&snapshot.call_stack);
+        self.inputs
+            .write()
+            .map_err(poisoned)?
+            .clone_from(&snapshot.inputs);
+        self.outputs
+            .write()
+            .map_err(poisoned)?
+            .clone_from(&snapshot.outputs);
+        self.pc.store(snapshot.pc, Ordering::SeqCst);
+        self.running.store(snapshot.running, Ordering::SeqCst);
+        self.waiting.store(snapshot.waiting, Ordering::SeqCst);
+        self.cycles.store(snapshot.cycles, Ordering::SeqCst);
+        self.peak_stack_depth
+            .store(snapshot.peak_stack_depth, Ordering::SeqCst);
+        self.memory_written
+            .write()
+            .map_err(poisoned)?
+            .clone_from(&snapshot.memory_written);
+        self.frames.store(snapshot.frames, Ordering::SeqCst);
+        self.frame_ready
+            .store(snapshot.frame_ready, Ordering::SeqCst);
+        self.rng.clone_from(&snapshot.rng);
+        Ok(())
+    }
     /// Parse some input text into a ast
     /// # Errors
-    /// This can return an Error if the text introduced here can't be parsed correctly
+    /// This can return an Error if the text introduced here can't be parsed correctly, or if it
+    /// uses instructions or registers the restrictions don't allow
     pub fn parse<T: AsRef<str>>(&mut self, contents: T) -> Result<(), InterpreterError> {
-        self.statements = ast_builder::parse_program(contents)?;
-        self.compile();
+        self.parse_source(contents.as_ref(), None)
+    }
+    /// Load the file `name` with the source loader and parse it. Files it includes are found
+    /// relative to it, and diagnostics say which file they're in
+    /// # Errors
+    /// Returns an error if there is no source loader or it can't load the file, or for anything
+    /// `parse` would return an error for
+    pub fn parse_file(&mut self, name: &str) -> Result<(), InterpreterError> {
+        let name = source::resolve(None, name);
+        let contents = match &self.source_loader {
+            Some(loader) => loader.load(&name),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "there is no source loader",
+            )),
+        }
+        .map_err(|error| InterpreterError::Load(name.clone(), error))?;
+        self.parse_source(&contents, Some(&name))
+    }
+    fn parse_source(&mut self, contents: &str, file: Option<&str>) -> Result<(), InterpreterError> {
+        (self.statements, self.spans) =
+            ast_builder::parse_program(contents, file, self.source_loader.as_deref())?
+                .into_iter()
+                .unzip();
+        self.check_restrictions()?;
+        self.compile()?;
         Ok(())
     }
-    /// Compile the parsed ast
-    fn compile(&mut self) {
-        for (i, statement) in self.statements.iter().enumerate() {
+    /// Make sure the program sticks to the restrictions
+    fn check_restrictions(&self) -> Result<(), InterpreterError> {
+        let diagnostics: Vec<Diagnostic> = self
+            .statements
+            .iter()
+            .zip(&self.spans)
+            .filter_map(|(statement, span)| Some((program_instruction(statement)?, span)))
+            .flat_map(|(instruction, span)| self.restrictions.check(instruction, span))
+            .collect();
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(InterpreterError::Compile(diagnostics))
+        }
+    }
+    /// Every instruction in the program, along with its `DATA`, `FILL` and `STRING` directives,
+    /// in the order they were written
+    pub(crate) fn instructions(&self) -> impl Iterator<Item = &Instruction> {
+        self.statements.iter().filter_map(program_instruction)
+    }
+    /// Compile the parsed ast: work out the constants and fold constant expressions into plain
+    /// operands, find the labels, and put the values from `DATA`, `FILL` and `STRING` in memory.
+    /// This goes through the program in order, so a constant can only be used after the `DEFINE`
+    /// for it
+    /// # Errors
+    /// Returns an error listing every constant that is undefined, used before it's defined or
+    /// defined twice, every expression that couldn't be worked out, and all data that can't be
+    /// put in memory
+    fn compile(&mut self) -> Result<(), InterpreterError> {
+        let mut definitions: HashMap<String, Span> = HashMap::new();
+        for (statement, span) in self.statements.iter().zip(&self.spans) {
+            if let Statement::CompileTime(
+                Instruction::Define { name, .. } | Instruction::Var { name, .. },
+            ) = statement
+            {
+                definitions.entry(name.clone()).or_insert(span.clone());
+            }
+        }
+        let mut label_spans: HashMap<String, Span> = HashMap::new();
+        let mut data: Vec<(Span, usize, DataRuns)> = Vec::new();
+        // `VAR` hands out memory upward from slot 0
+        let mut next_free_slot = 0;
+        let mut failed: HashSet<String> = HashSet::new();
+        let mut diagnostics = self.scope_labels();
+        for (i, (statement, span)) in self.statements.iter_mut().zip(&self.spans).enumerate() {
+            let scope = ConstantScope {
+                defined: &self.constants,
+                definitions: &definitions,
+                failed: &failed,
+            };
             match statement {
-                Statement::CompileTime(instr) => match instr {
-                    Instruction::Define { name, value } => {
-                        let resolved = resolve_operand_compile(value, &self.constants);
-                        if let Some(val) = resolved {
-                            self.constants.insert(name.clone(), val);
+                Statement::CompileTime(Instruction::Define { name, value }) => {
+                    let result = first_definition(name, span, &definitions).and_then(|()| {
+                        constant_value(value, &scope)
+                            .map_err(|message| format!("Can't define {name}: {message}"))
+                    });
+                    match result {
+                        Ok(val) => _ = self.constants.insert(name.clone(), val),
+                        Err(message) => {
+                            failed.insert(name.clone());
+                            diagnostics.push(Diagnostic {
+                                span: span.clone(),
+                                message,
+                            });
+                        }
+                    }
+                }
+                Statement::CompileTime(Instruction::Var { name, size }) => {
+                    let result = first_definition(name, span, &definitions)
+                        .and_then(|()| allocate(name, size.as_ref(), &mut next_free_slot, &scope));
+                    match result {
+                        Ok(address) => _ = self.constants.insert(name.clone(), address),
+                        Err(message) => {
+                            failed.insert(name.clone());
+                            diagnostics.push(Diagnostic {
+                                span: span.clone(),
+                                message,
+                            });
                         }
                     }
-                    _ => unimplemented!(),
+                }
+                Statement::CompileTime(directive) => match data_runs(directive, &scope) {
+                    Ok((address, runs)) => data.push((span.clone(), address, runs)),
+                    Err(message) => diagnostics.push(Diagnostic {
+                        span: span.clone(),
+                        message,
+                    }),
                 },
-                Statement::Label(name) => _ = self.labels.insert(name.clone(), i),
-                Statement::Instruction(_) => {}
+                Statement::Label(name) => {
+                    if let Some(first) = label_spans.get(name) {
+                        diagnostics.push(Diagnostic {
+                            span: span.clone(),
+                            message: format!(
+                                "The label {} is already defined on {first}",
+                                label_name(name)
+                            ),
+                        });
+                    } else {
+                        label_spans.insert(name.clone(), span.clone());
+                        self.labels.insert(name.clone(), i);
+                    }
+                }
+                Statement::Instruction(instruction) => {
+                    for operand in instruction.operands_mut() {
+                        if let Err(message) = fold_operand(operand, &scope) {
+                            diagnostics.push(Diagnostic {
+                                span: span.clone(),
+                                message,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        for (span, start, runs) in data {
+            if let Err(message) = self.write_data(start, runs) {
+                diagnostics.push(Diagnostic { span, message });
+            }
+        }
+        diagnostics.extend(self.check_labels());
+        diagnostics.sort_by(|a, b| a.span.cmp(&b.span));
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(InterpreterError::Compile(diagnostics))
+        }
+    }
+    /// Put runs of values in memory one after another, starting at `start`
+    /// # Errors
+    /// Returns an error for the first address that can't be written to
+    fn write_data(&mut self, start: usize, runs: DataRuns) -> Result<(), String> {
+        let mut address = start;
+        for (value, count) in runs {
+            for _ in 0..count {
+                self.set_address(format!("%{address}"), value.clone())
+                    .map_err(|e| format!("Can't put data at %{address}: {e}"))?;
+                address += 1;
+            }
+        }
+        Ok(())
+    }
+    /// Give local and anonymous labels names of their own, and point every `JMP` and `CALL` that
+    /// goes to one at that name. A local label like `@loop` belongs to the last global label
+    /// before it in the same file, `JMP +` goes to the next `+:` label and `JMP -` to the last
+    /// `-:` label, with `++` and `--` going one further. Anonymous labels are only found in the
+    /// same file and the same macro call as the jump
+    fn scope_labels(&mut self) -> Vec<Diagnostic> {
+        let mut scopes: HashMap<Option<Arc<str>>, String> = HashMap::new();
+        let mut forward: HashMap<AnonymousScope, Vec<(usize, String)>> = HashMap::new();
+        let mut backward: HashMap<AnonymousScope, Vec<String>> = HashMap::new();
+        let mut pending: Vec<usize> = Vec::new();
+        let mut diagnostics = Vec::new();
+        for (i, (statement, span)) in self.statements.iter_mut().zip(&self.spans).enumerate() {
+            let scope = scopes.entry(span.file.clone()).or_default();
+            let anonymous = (span.file.clone(), span.expansion.clone());
+            match statement {
+                Statement::Label(name) if name == "+" => {
+                    *name = format!("+{i}");
+                    forward
+                        .entry(anonymous.clone())
+                        .or_default()
+                        .push((i, name.clone()));
+                }
+                Statement::Label(name) if name == "-" => {
+                    *name = format!("-{i}");
+                    backward
+                        .entry(anonymous.clone())
+                        .or_default()
+                        .push(name.clone());
+                }
+                Statement::Label(name) if name.starts_with('@') => {
+                    *name = format!("{scope}{name}");
+                }
+                // Global labels from inside a macro don't start a scope, so local labels after
+                // the call still belong to the caller's
+                Statement::Label(name) if span.expansion.is_none() => scope.clone_from(name),
+                _ => {}
+            }
+            let Some(target) = label_target(statement) else {
+                continue;
+            };
+            if target.starts_with('@') {
+                *target = format!("{scope}{target}");
+            } else if target.starts_with('+') {
+                pending.push(i);
+            } else if target.starts_with('-') {
+                let labels = backward.get(&anonymous).map_or(&[][..], Vec::as_slice);
+                match labels.len().checked_sub(target.len()) {
+                    Some(found) => labels[found].clone_into(target),
+                    None => diagnostics.push(Diagnostic {
+                        span: span.clone(),
+                        message: missing_anonymous_label(target),
+                    }),
+                }
+            }
+        }
+        for i in pending {
+            let Some(target) = label_target(&mut self.statements[i]) else {
+                continue;
+            };
+            let span = &self.spans[i];
+            match forward
+                .get(&(span.file.clone(), span.expansion.clone()))
+                .into_iter()
+                .flatten()
+                .filter(|(at, _)| *at > i)
+                .nth(target.len() - 1)
+            {
+                Some((_, label)) => label.clone_into(target),
+                None => diagnostics.push(Diagnostic {
+                    span: span.clone(),
+                    message: missing_anonymous_label(target),
+                }),
             }
         }
+        diagnostics
     }
-    /// Run the actual program that's been parsed
+    /// Make sure every label that `JMP` and `CALL` go to exists, suggesting a label with a
+    /// similar name when it doesn't
+    fn check_labels(&self) -> Vec<Diagnostic> {
+        let mut names: Vec<&str> = self.labels.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        self.statements
+            .iter()
+            .zip(&self.spans)
+            .filter_map(|(statement, span)| {
+                let Statement::Instruction(
+                    Instruction::Jmp { target, .. } | Instruction::Call { target },
+                ) = statement
+                else {
+                    return None;
+                };
+                let Operand::Identifier(label) = target else {
+                    return None;
+                };
+                // Anonymous labels that are left have already been reported by `scope_labels`
+                if self.labels.contains_key(label) || label.starts_with(['+', '-']) {
+                    return None;
+                }
+                let message = match closest_match(label, names.iter().copied()) {
+                    Some(suggestion) => format!(
+                        "The label {} is not defined. Did you mean {}?",
+                        label_name(label),
+                        label_name(suggestion)
+                    ),
+                    None => format!("The label {} is not defined", label_name(label)),
+                };
+                Some(Diagnostic {
+                    span: span.clone(),
+                    message,
+                })
+            })
+            .collect()
+    }
+    /// Run the actual program that's been parsed, until it halts or is blocked waiting for input
     /// # Panics
     /// If any lock is poisoned, the code can panic. This is **probably** okay most of the time
     pub fn run(&mut self) {
@@ -129,6 +648,9 @@ impl Interpreter {
                 self.running.store(false, Ordering::SeqCst);
                 break;
             }
+            if self.waiting.load(Ordering::SeqCst) {
+                break;
+            }
             let pc = self.pc.load(Ordering::SeqCst);
             if pc < self.statements.len() {
                 println!(
@@ -167,6 +689,7 @@ impl Interpreter {
     /// # Errors
     /// This can return an error if some operand is not possible to run. This could be things like
     /// setting a non-existant memory address, or a division by zero
+    #[allow(clippy::too_many_lines)]
     pub fn step(&mut self) -> Result<(), InterpreterError> {
         let pc = self.pc.load(Ordering::SeqCst);
         if pc >= self.statements.len() {
@@ -177,7 +700,11 @@ impl Interpreter {
         match &self.statements[pc].clone() {
             Statement::Label(_) | Statement::CompileTime(_) => {}
             Statement::Instruction(instruction) => match instruction {
-                Instruction::Define { name: _, value: _ } => {}
+                Instruction::Define { .. }
+                | Instruction::Var { .. }
+                | Instruction::Data { .. }
+                | Instruction::DataFill { .. }
+                | Instruction::DataString { .. } => {}
                 Instruction::Set { value, dest } => {
                     let val = self
                         .get_operand_value(value)
@@ -191,12 +718,90 @@ impl Interpreter {
                 Instruction::Sub { left, right } => self.execute_sub(left, right)?,
                 Instruction::Mul { left, right } => self.execute_mul(left, right)?,
                 Instruction::Div { left, right } => self.execute_div(left, right)?,
+                Instruction::FloorDiv { left, right } => {
+                    self.execute_division(left, right, Value::floor_div_with)?;
+                }
+                Instruction::Mod { left, right } => {
+                    self.execute_division(left, right, Value::rem_with)?;
+                }
+                Instruction::DivMod {
+                    left,
+                    right,
+                    remainder,
+                } => self.execute_divmod(left, right, remainder)?,
+                Instruction::Neg { op } => self.execute_unary(op, Value::neg_with)?,
+                Instruction::Abs { op } => self.execute_unary(op, Value::abs_with)?,
+                Instruction::Min { left, right } => {
+                    self.execute_binary(left, right, Value::min)?;
+                }
+                Instruction::Max { left, right } => {
+                    self.execute_binary(left, right, Value::max)?;
+                }
                 Instruction::Inc { dest } => self.execute_inc(dest)?,
                 Instruction::Dec { dest } => self.execute_dec(dest)?,
                 Instruction::And { left, right } => self.execute_and(left, right)?,
                 Instruction::Or { left, right } => self.execute_or(left, right)?,
                 Instruction::Xor { left, right } => self.execute_xor(left, right)?,
                 Instruction::Not { op } => self.execute_not(op)?,
+                Instruction::Shift {
+                    kind,
+                    value,
+                    amount,
+                } => self.execute_shift(*kind, value, amount)?,
+                Instruction::Pixel { x, y, colour } => self.execute_pixel(x, y, colour)?,
+                Instruction::Fill { colour } => self.execute_fill(colour)?,
+                Instruction::Line {
+                    x0,
+                    y0,
+                    x1,
+                    y1,
+                    colour,
+                } => self.execute_line((x0, y0), (x1, y1), colour)?,
+                Instruction::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                    colour,
+                } => self.execute_rect((x, y), (width, height), colour)?,
+                Instruction::Flip => self.execute_flip()?,
+                Instruction::In { port, dest } => increment_pc = self.execute_in(port, dest)?,
+                Instruction::Out { src, port } => self.execute_out(src, port)?,
+                Instruction::Print { src, newline } => self.execute_print(src, *newline)?,
+                Instruction::Read { dest, line } => self.execute_read(dest, *line)?,
+                Instruction::Rand { dest, low, high } => self.execute_rand(dest, low, high)?,
+                Instruction::Len { src } => self.execute_map(src, Value::len)?,
+                Instruction::Upper { src } => self.execute_map(src, Value::upper)?,
+                Instruction::Lower { src } => self.execute_map(src, Value::lower)?,
+                Instruction::Trim { src } => self.execute_map(src, Value::trim)?,
+                Instruction::Reverse { src } => self.execute_map(src, Value::reverse)?,
+                Instruction::Ord { src } => self.execute_map(src, Value::ord)?,
+                Instruction::Chr { src } => self.execute_map(src, Value::chr)?,
+                Instruction::Type { src } => {
+                    self.execute_map(src, |value| Ok(value.type_code()))?;
+                }
+                Instruction::Itoa { src, radix } => {
+                    let radix = self.get_radix(radix.as_ref())?.unwrap_or(10);
+                    self.execute_map(src, |value| value.itoa(radix))?;
+                }
+                Instruction::Atoi { src, radix } => {
+                    let radix = self.get_radix(radix.as_ref())?;
+                    self.execute_map(src, |value| value.atoi(radix))?;
+                }
+                Instruction::CharAt { src, index } => {
+                    self.execute_binary(src, index, Value::char_at)?;
+                }
+                Instruction::Find { haystack, needle } => {
+                    self.execute_binary(haystack, needle, Value::find)?;
+                }
+                Instruction::Substr { src, start, length } => {
+                    self.execute_substr(src, start, length)?;
+                }
+                Instruction::Split {
+                    src,
+                    separator,
+                    dest,
+                } => self.execute_split(src, separator, dest)?,
                 Instruction::Jmp { target, comparison } => {
                     if let Operand::Identifier(label) = target {
                         self.execute_jump(label, comparison.as_ref())?;
@@ -231,6 +836,11 @@ impl Interpreter {
             },
         }
 
+        if matches!(self.statements[pc], Statement::Instruction(_))
+            && !self.waiting.load(Ordering::SeqCst)
+        {
+            self.cycles.fetch_add(1, Ordering::SeqCst);
+        }
         if increment_pc {
             self.pc.store(pc + 1, Ordering::SeqCst);
         }
@@ -300,10 +910,11 @@ impl Interpreter {
             .ok_or(InterpreterError::InvalidOperand(format!(
                 "Could not resolve value of operand '{right:?}'"
             )))?;
-        let value = Value::add(&left_val, &right_val).map_err(|_| {
-            InterpreterError::TypeMismatch(Box::new((left_val.clone(), right_val.clone())))
-        })?;
-        self.set_operand_value(&Operand::Register(ACC.to_string()), &value)
+        let (value, overflowed) = left_val
+            .add_with(&right_val, self.arithmetic())
+            .map_err(value_error)?;
+        self.set_operand_value(&Operand::Register(ACC.to_string()), &value)?;
+        self.set_flag(FLAG_OVERFLOW, overflowed)
     }
     /// Subtract two `Operand`
     /// # Errors
@@ -320,10 +931,11 @@ impl Interpreter {
             .ok_or(InterpreterError::InvalidOperand(format!(
                 "Could not resolve value of operand '{right:?}'"
             )))?;
-        let value = Value::sub(&left_val, &right_val).map_err(|_| {
-            InterpreterError::TypeMismatch(Box::new((left_val.clone(), right_val.clone())))
-        })?;
-        self.set_operand_value(&Operand::Register(ACC.to_string()), &value)
+        let (value, overflowed) = left_val
+            .sub_with(&right_val, self.arithmetic())
+            .map_err(value_error)?;
+        self.set_operand_value(&Operand::Register(ACC.to_string()), &value)?;
+        self.set_flag(FLAG_OVERFLOW, overflowed)
     }
     fn execute_mul(&mut self, left: &Operand, right: &Operand) -> Result<(), InterpreterError> {
         let left_val = self
@@ -337,12 +949,26 @@ impl Interpreter {
             .ok_or(InterpreterError::InvalidOperand(format!(
                 "Could not resolve value of operand '{right:?}'"
             )))?;
-        let value = Value::mul(&left_val, &right_val).map_err(|_| {
-            InterpreterError::TypeMismatch(Box::new((left_val.clone(), right_val.clone())))
-        })?;
-        self.set_operand_value(&Operand::Register(ACC.to_string()), &value)
+        let (value, overflowed) = left_val
+            .mul_with(&right_val, self.arithmetic())
+            .map_err(value_error)?;
+        self.set_operand_value(&Operand::Register(ACC.to_string()), &value)?;
+        self.set_flag(FLAG_OVERFLOW, overflowed)
     }
+    /// Divide two `Operand`, truncating the quotient
+    /// # Errors
+    /// Errors when dividing by zero, when either `Operand` can't be loaded, or when the quotient
+    /// overflows in checked arithmetic
     fn execute_div(&mut self, left: &Operand, right: &Operand) -> Result<(), InterpreterError> {
+        self.execute_division(left, right, Value::div_with)
+    }
+    /// Divide two `Operand` with `operation`, which either truncates or floors
+    fn execute_division(
+        &mut self,
+        left: &Operand,
+        right: &Operand,
+        operation: BinaryArithmetic,
+    ) -> Result<(), InterpreterError> {
         let left_val = self
             .get_operand_value(left)
             .ok_or(InterpreterError::InvalidOperand(format!(
@@ -354,12 +980,57 @@ impl Interpreter {
             .ok_or(InterpreterError::InvalidOperand(format!(
                 "Could not resolve value of operand '{right:?}'"
             )))?;
-        let value = Value::div(&left_val, &right_val).map_err(|e| match e {
-            ValueError::DivisionByZero(a, b) => InterpreterError::DivisionByZero(a, b),
-            _ => InterpreterError::TypeMismatch(Box::new((left_val.clone(), right_val.clone()))),
-        })?;
+        let (value, overflowed) =
+            operation(&left_val, &right_val, self.arithmetic()).map_err(value_error)?;
+        self.set_operand_value(&Operand::Register(ACC.to_string()), &value)?;
+        self.set_flag(FLAG_OVERFLOW, overflowed)
+    }
+    /// Store `DIVMOD`'s quotient in the accumulator and its remainder in `remainder`, and whether
+    /// the quotient overflowed in the overflow flag
+    /// # Errors
+    /// Errors when dividing by zero, or if either `Operand` isn't a number
+    fn execute_divmod(
+        &mut self,
+        left: &Operand,
+        right: &Operand,
+        remainder: &Operand,
+    ) -> Result<(), InterpreterError> {
+        let left_val = self.get_value(left)?;
+        let right_val = self.get_value(right)?;
+        let (quotient, rest, overflowed) =
+            Value::divmod_with(&left_val, &right_val, self.arithmetic()).map_err(value_error)?;
+        self.set_operand_value(&Operand::Register(ACC.to_string()), &quotient)?;
+        self.set_operand_value(remainder, &rest)?;
+        self.set_flag(FLAG_OVERFLOW, overflowed)
+    }
+    /// Apply `operation` to two `Operand` and store the result in the accumulator
+    /// # Errors
+    /// Errors when either `Operand` can't be loaded, or when `operation` fails
+    fn execute_binary(
+        &mut self,
+        left: &Operand,
+        right: &Operand,
+        operation: fn(&Value, &Value) -> Result<Value, ValueError>,
+    ) -> Result<(), InterpreterError> {
+        let left_val = self.get_value(left)?;
+        let right_val = self.get_value(right)?;
+        let value = operation(&left_val, &right_val).map_err(value_error)?;
         self.set_operand_value(&Operand::Register(ACC.to_string()), &value)
     }
+    /// Apply the arithmetic `operation` to an `Operand`, storing the result in the accumulator
+    /// and whether it overflowed in the overflow flag
+    /// # Errors
+    /// Errors when the `Operand` can't be loaded, or when `operation` fails
+    fn execute_unary(
+        &mut self,
+        src: &Operand,
+        operation: UnaryArithmetic,
+    ) -> Result<(), InterpreterError> {
+        let val = self.get_value(src)?;
+        let (value, overflowed) = operation(&val, self.arithmetic()).map_err(value_error)?;
+        self.set_operand_value(&Operand::Register(ACC.to_string()), &value)?;
+        self.set_flag(FLAG_OVERFLOW, overflowed)
+    }
     fn execute_inc(&mut self, dest: &Operand) -> Result<(), InterpreterError> {
         let value = self
             .get_operand_value(dest)
@@ -367,10 +1038,11 @@ impl Interpreter {
                 "Could not resolve value of operand '{dest:?}'"
             )))?;
 
-        let result = Value::add(&value, &Value::Number(1)).map_err(|_| {
-            InterpreterError::TypeMismatch(Box::new((value.clone(), Value::Number(1))))
-        })?;
-        self.set_operand_value(dest, &result)
+        let (result, overflowed) = value
+            .add_with(&Value::Number(1), self.arithmetic())
+            .map_err(value_error)?;
+        self.set_operand_value(dest, &result)?;
+        self.set_flag(FLAG_OVERFLOW, overflowed)
     }
     fn execute_dec(&mut self, dest: &Operand) -> Result<(), InterpreterError> {
         let value = self
@@ -379,10 +1051,11 @@ impl Interpreter {
                 "Could not resolve value of operand '{dest:?}'"
             )))?;
 
-        let result = Value::sub(&value, &Value::Number(1)).map_err(|_| {
-            InterpreterError::TypeMismatch(Box::new((value.clone(), Value::Number(1))))
-        })?;
-        self.set_operand_value(dest, &result)
+        let (result, overflowed) = value
+            .sub_with(&Value::Number(1), self.arithmetic())
+            .map_err(value_error)?;
+        self.set_operand_value(dest, &result)?;
+        self.set_flag(FLAG_OVERFLOW, overflowed)
     }
 
     fn execute_and(&mut self, left: &Operand, right: &Operand) -> Result<(), InterpreterError> {
@@ -448,6 +1121,280 @@ impl Interpreter {
         })?;
         self.set_operand_value(&Operand::Register(ACC.to_string()), &value)
     }
+    /// Shift or rotate a number, storing the result in the accumulator and the bit shifted out in
+    /// the carry flag
+    /// # Errors
+    /// Errors if either `Operand` isn't a number, or if the amount is negative
+    fn execute_shift(
+        &mut self,
+        kind: ShiftKind,
+        value: &Operand,
+        amount: &Operand,
+    ) -> Result<(), InterpreterError> {
+        let value_val = self.get_value(value)?;
+        let amount_val = self.get_value(amount)?;
+        let (result, carry) = value_val
+            .shift(&amount_val, kind, self.word_width)
+            .map_err(value_error)?;
+        self.set_operand_value(&Operand::Register(ACC.to_string()), &result)?;
+        self.set_flag(FLAG_CARRY, carry)
+    }
+    /// Set or clear one of the bits in the F register
+    /// # Errors
+    /// Errors if the F register doesn't hold a number
+    fn set_flag(&mut self, flag: i64, set: bool) -> Result<(), InterpreterError> {
+        let flags = match self.get_register(FLAGS)? {
+            Value::Number(flags) => flags,
+            value @ Value::String(_) => {
+                return Err(InterpreterError::TypeMismatch(Box::new((
+                    value,
+                    Value::Number(0),
+                ))));
+            }
+        };
+        let flags = if set { flags | flag } else { flags & !flag };
+        self.set_register(FLAGS, Value::Number(flags))
+    }
+    /// Set a single pixel on the display
+    /// # Errors
+    /// Errors if the coordinates are outside of the display or the colour is invalid
+    fn execute_pixel(
+        &mut self,
+        x: &Operand,
+        y: &Operand,
+        colour: &Operand,
+    ) -> Result<(), InterpreterError> {
+        let x = self.get_number(x)?;
+        let y = self.get_number(y)?;
+        let colour = self.get_colour(colour)?;
+        let mut display = self
+            .display
+            .write()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
+        if plot(&mut display, x, y, colour) {
+            Ok(())
+        } else {
+            Err(InterpreterError::InvalidPixel(x, y))
+        }
+    }
+    fn execute_fill(&mut self, colour: &Operand) -> Result<(), InterpreterError> {
+        let colour = self.get_colour(colour)?;
+        self.display
+            .write()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?
+            .fill(colour);
+        Ok(())
+    }
+    /// Draw a line between two points on the display, including both end points
+    /// # Errors
+    /// Errors if either end point is outside of the display or the colour is invalid
+    fn execute_line(
+        &mut self,
+        start: (&Operand, &Operand),
+        end: (&Operand, &Operand),
+        colour: &Operand,
+    ) -> Result<(), InterpreterError> {
+        let (mut x, mut y) = (self.get_number(start.0)?, self.get_number(start.1)?);
+        let (x1, y1) = (self.get_number(end.0)?, self.get_number(end.1)?);
+        let colour = self.get_colour(colour)?;
+        for (px, py) in [(x, y), (x1, y1)] {
+            if !on_display(px, py) {
+                return Err(InterpreterError::InvalidPixel(px, py));
+            }
+        }
+        let mut display = self
+            .display
+            .write()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
+
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let step_x = if x < x1 { 1 } else { -1 };
+        let step_y = if y < y1 { 1 } else { -1 };
+        let mut error = dx + dy;
+        loop {
+            plot(&mut display, x, y, colour);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+        Ok(())
+    }
+    /// Draw a filled rectangle. Anything that would be drawn past the edge of the display is
+    /// clipped
+    /// # Errors
+    /// Errors if the top left corner is outside of the display or the colour is invalid
+    fn execute_rect(
+        &mut self,
+        corner: (&Operand, &Operand),
+        size: (&Operand, &Operand),
+        colour: &Operand,
+    ) -> Result<(), InterpreterError> {
+        let (x, y) = (self.get_number(corner.0)?, self.get_number(corner.1)?);
+        let (width, height) = (self.get_number(size.0)?, self.get_number(size.1)?);
+        let colour = self.get_colour(colour)?;
+        if !on_display(x, y) {
+            return Err(InterpreterError::InvalidPixel(x, y));
+        }
+        let mut display = self
+            .display
+            .write()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
+        for py in (y..y.saturating_add(height)).take(DISPLAY_HEIGHT) {
+            for px in (x..x.saturating_add(width)).take(DISPLAY_WIDTH) {
+                plot(&mut display, px, py, colour);
+            }
+        }
+        Ok(())
+    }
+    /// Signal to the host that a frame has been drawn
+    fn execute_flip(&mut self) -> Result<(), InterpreterError> {
+        let frame = self.frames.fetch_add(1, Ordering::SeqCst) + 1;
+        self.frame_ready.store(true, Ordering::SeqCst);
+        if let Some(callback) = self.on_frame.as_mut() {
+            let display = self
+                .display
+                .read()
+                .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
+            callback(frame, &display);
+        }
+        Ok(())
+    }
+    /// Read the next value from an input port. If the port is empty, nothing is read and this
+    /// returns `false` so that the instruction is tried again on the next step
+    fn execute_in(&mut self, port: &Operand, dest: &Operand) -> Result<bool, InterpreterError> {
+        let port = self.get_port(port)?;
+        let value = self
+            .inputs
+            .write()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?[port]
+            .pop_front();
+        self.waiting.store(value.is_none(), Ordering::SeqCst);
+        match value {
+            Some(value) => {
+                self.set_operand_value(dest, &value)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    fn execute_out(&mut self, src: &Operand, port: &Operand) -> Result<(), InterpreterError> {
+        let port = self.get_port(port)?;
+        let value = self
+            .get_operand_value(src)
+            .ok_or(InterpreterError::InvalidOperand(format!(
+                "Could not resolve value of operand '{src:?}'"
+            )))?;
+        self.outputs
+            .write()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?[port]
+            .push_back(value);
+        Ok(())
+    }
+    fn execute_print(&mut self, src: &Operand, newline: bool) -> Result<(), InterpreterError> {
+        let value = self
+            .get_operand_value(src)
+            .ok_or(InterpreterError::InvalidOperand(format!(
+                "Could not resolve value of operand '{src:?}'"
+            )))?;
+        if newline {
+            writeln!(self.output, "{value}")?;
+        } else {
+            write!(self.output, "{value}")?;
+        }
+        self.output.flush()?;
+        Ok(())
+    }
+    /// Read a line of input. `READLINE` always stores it as a string, while `READ` stores a number
+    /// if the line is one, and the trimmed line otherwise
+    /// # Errors
+    /// Returns an error when there is no input left
+    fn execute_read(&mut self, dest: &Operand, line: bool) -> Result<(), InterpreterError> {
+        let mut input = String::new();
+        if self.input.read_line(&mut input)? == 0 {
+            return Err(InterpreterError::EndOfInput);
+        }
+        let input = input.strip_suffix('\n').unwrap_or(&input);
+        let input = input.strip_suffix('\r').unwrap_or(input);
+        let value = if line {
+            Value::String(input.to_string())
+        } else {
+            convert_string_to_num(input)
+                .map_or_else(|_| Value::String(input.trim().to_string()), Value::Number)
+        };
+        self.set_operand_value(dest, &value)
+    }
+    /// Store a random number from `low` to `high`, including both
+    fn execute_rand(
+        &mut self,
+        dest: &Operand,
+        low: &Operand,
+        high: &Operand,
+    ) -> Result<(), InterpreterError> {
+        let low = self.get_number(low)?;
+        let high = self.get_number(high)?;
+        if low > high {
+            return Err(InterpreterError::InvalidOperand(format!(
+                "RAND range {low}..={high} is empty"
+            )));
+        }
+        let value = Value::Number(self.rng.range(low, high));
+        self.set_operand_value(dest, &value)
+    }
+    /// Apply `operation` to an `Operand` and store the result in the accumulator
+    /// # Errors
+    /// Errors when the `Operand` can't be loaded or `operation` fails
+    fn execute_map(
+        &mut self,
+        src: &Operand,
+        operation: impl Fn(&Value) -> Result<Value, ValueError>,
+    ) -> Result<(), InterpreterError> {
+        let val = self.get_value(src)?;
+        let value = operation(&val).map_err(value_error)?;
+        self.set_operand_value(&Operand::Register(ACC.to_string()), &value)
+    }
+    fn execute_substr(
+        &mut self,
+        src: &Operand,
+        start: &Operand,
+        length: &Operand,
+    ) -> Result<(), InterpreterError> {
+        let val = self.get_value(src)?;
+        let start = self.get_value(start)?;
+        let length = self.get_value(length)?;
+        let value = val.substr(&start, &length).map_err(value_error)?;
+        self.set_operand_value(&Operand::Register(ACC.to_string()), &value)
+    }
+    /// Split a string, storing the pieces in memory starting at `dest` and how many there are in
+    /// the accumulator
+    /// # Errors
+    /// Errors when either `Operand` isn't a string, or the pieces don't fit in memory
+    fn execute_split(
+        &mut self,
+        src: &Operand,
+        separator: &Operand,
+        dest: &Operand,
+    ) -> Result<(), InterpreterError> {
+        let val = self.get_value(src)?;
+        let separator = self.get_value(separator)?;
+        let pieces = val.split(&separator).map_err(value_error)?;
+        let start = self.get_memory_address(dest)?;
+        for (i, piece) in pieces.iter().enumerate() {
+            self.set_address((start + i).to_string(), piece.clone())?;
+        }
+        let count =
+            i64::try_from(pieces.len()).map_err(|e| InterpreterError::Other(format!("{e}")))?;
+        self.set_operand_value(&Operand::Register(ACC.to_string()), &Value::Number(count))
+    }
     fn execute_jump(
         &mut self,
         label: &String,
@@ -526,10 +1473,13 @@ impl Interpreter {
     fn execute_push(&mut self, src: &Operand) -> Result<(), InterpreterError> {
         let val = self.get_operand_value(src).unwrap_or_default();
 
-        self.stack
+        let mut stack = self
+            .stack
             .write()
-            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?
-            .push(val);
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
+        stack.push(val);
+        self.peak_stack_depth
+            .fetch_max(stack.len(), Ordering::SeqCst);
 
         Ok(())
     }
@@ -578,10 +1528,84 @@ impl Interpreter {
             Operand::String(s) => Some(Value::String(s.clone())),
             Operand::Memory(s) => self.get_address(s).ok(),
             Operand::Register(r) | Operand::IndirectMemory(r) => self.get_register(r).ok(),
-            Operand::Identifier(_) => None,
+            // Expressions are folded into plain operands when the program is compiled
+            Operand::Identifier(_) | Operand::Expression(_) | Operand::MemoryExpression(_) => None,
+        }
+    }
+
+    /// Get the value of an `Operand`
+    /// # Errors
+    /// Errors if the `Operand` can't be resolved
+    fn get_value(&self, operand: &Operand) -> Result<Value, InterpreterError> {
+        self.get_operand_value(operand)
+            .ok_or(InterpreterError::InvalidOperand(format!(
+                "Could not resolve value of operand '{operand:?}'"
+            )))
+    }
+    /// The radix an `Operand` holds, if there is one
+    /// # Errors
+    /// Errors if the `Operand` isn't a number, or is negative
+    fn get_radix(&self, operand: Option<&Operand>) -> Result<Option<u32>, InterpreterError> {
+        operand
+            .map(|operand| {
+                let radix = self.get_number(operand)?;
+                u32::try_from(radix)
+                    .map_err(|_| InterpreterError::Other(format!("Invalid radix {radix}")))
+            })
+            .transpose()
+    }
+    /// The address a memory `Operand` points to. Indirect addresses like `%R2` use the address
+    /// held in the register
+    /// # Errors
+    /// Errors if the `Operand` isn't a memory address, or the address is negative
+    fn get_memory_address(&self, operand: &Operand) -> Result<usize, InterpreterError> {
+        let address = match operand {
+            Operand::Memory(address) => {
+                let number = address.strip_prefix('%').unwrap_or(address);
+                convert_string_to_num(number).map_err(|e| {
+                    InterpreterError::InvalidMemoryAddress(format!("{address}: {e}"))
+                })?
+            }
+            Operand::Register(_) | Operand::IndirectMemory(_) => self.get_number(operand)?,
+            _ => {
+                return Err(InterpreterError::InvalidMemoryAddress(format!(
+                    "'{operand}' is not a memory address"
+                )));
+            }
+        };
+        usize::try_from(address).map_err(|_| {
+            InterpreterError::InvalidMemoryAddress(format!("Negative memory address {address}"))
+        })
+    }
+    /// Get the value of an `Operand` that has to be a number
+    /// # Errors
+    /// Errors if the `Operand` can't be resolved or holds a string
+    fn get_number(&self, operand: &Operand) -> Result<i64, InterpreterError> {
+        match self.get_operand_value(operand) {
+            Some(Value::Number(n)) => Ok(n),
+            Some(value) => Err(InterpreterError::TypeMismatch(Box::new((
+                value,
+                Value::Number(0),
+            )))),
+            None => Err(InterpreterError::InvalidOperand(format!(
+                "Could not resolve value of operand '{operand:?}'"
+            ))),
         }
     }
 
+    fn get_port(&self, operand: &Operand) -> Result<usize, InterpreterError> {
+        let port = self.get_number(operand)?;
+        usize::try_from(port)
+            .ok()
+            .filter(|port| *port < PORTS)
+            .ok_or(InterpreterError::InvalidPort(port.to_string()))
+    }
+
+    fn get_colour(&self, operand: &Operand) -> Result<(i32, i32, i32), InterpreterError> {
+        let colour = self.get_number(operand)?;
+        unpack_colour(colour).ok_or(InterpreterError::InvalidColour(colour))
+    }
+
     fn set_operand_value(
         &mut self,
         operand: &Operand,
@@ -594,17 +1618,59 @@ impl Interpreter {
             }
             Operand::Constant(s) => Err(InterpreterError::CannotSetConstant(s.clone())),
             Operand::Identifier(i) => Err(InterpreterError::CannotSetIdentifier(i.clone())),
-            Operand::Number(_) | Operand::Character(_) | Operand::String(_) => Err(
-                InterpreterError::InvalidOperand("Cannot set operand, invalid type".to_string()),
-            ),
+            Operand::Number(_)
+            | Operand::Character(_)
+            | Operand::String(_)
+            | Operand::Expression(_)
+            | Operand::MemoryExpression(_) => Err(InterpreterError::InvalidOperand(
+                "Cannot set operand, invalid type".to_string(),
+            )),
         }
     }
 
-    fn get_address<T: AsRef<str> + Display>(&self, addr: T) -> Result<Value, InterpreterError> {
-        let address =
-            addr.as_ref()
-                .strip_prefix('%')
-                .ok_or(InterpreterError::InvalidMemoryAddress(
+    /// How many different RAM addresses the program has written to
+    /// # Errors
+    /// Returns an error if the lock is poisoned
+    pub fn memory_slots_used(&self) -> Result<usize, InterpreterError> {
+        Ok(self
+            .memory_written
+            .read()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?
+            .len())
+    }
+    /// Read from an address, which can be in RAM or in a region mapped above it
+    /// # Errors
+    /// Returns an error if nothing is mapped at the address
+    pub fn read_memory(&self, address: usize) -> Result<Value, InterpreterError> {
+        if address >= RAM_SLOTS {
+            return self.memory_map.read(address);
+        }
+        let memory = self
+            .memory
+            .read()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
+        Ok(memory[address].clone())
+    }
+    /// Write to an address, which can be in RAM or in a region mapped above it
+    /// # Errors
+    /// Returns an error if nothing is mapped at the address, or the region rejects the value
+    pub fn write_memory(&self, address: usize, value: Value) -> Result<(), InterpreterError> {
+        if address >= RAM_SLOTS {
+            return self.memory_map.write(address, value);
+        }
+        let mut memory = self
+            .memory
+            .write()
+            .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
+        memory[address] = value;
+        Ok(())
+    }
+
+    fn get_address<T: AsRef<str> + Display>(&self, addr: T) -> Result<Value, InterpreterError> {
+        let address =
+            addr.as_ref()
+                .strip_prefix('%')
+                .ok_or(InterpreterError::InvalidMemoryAddress(
                     addr.as_ref().to_string(),
                 ))?;
         match convert_string_to_num(address) {
@@ -614,17 +1680,7 @@ impl Interpreter {
                         "Negative memory address {address}"
                     ))
                 })?;
-                if index >= RAM_SLOTS {
-                    return Err(InterpreterError::InvalidMemoryAddress(format!(
-                        "Address '{index}' out of range"
-                    )));
-                }
-                let memory = self
-                    .memory
-                    .read()
-                    .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
-                let value = memory[index].clone();
-                Ok(value)
+                self.read_memory(index)
             }
             Err(e) => Err(InterpreterError::InvalidMemoryAddress(format!(
                 "Invalid address: {address:?} - {e}"
@@ -657,25 +1713,20 @@ impl Interpreter {
                         "Negative memory address {address}"
                     ))
                 })?;
-                if index >= RAM_SLOTS {
-                    return Err(InterpreterError::InvalidMemoryAddress(format!(
-                        "Address '{index}' out of range"
-                    )));
+                if index < RAM_SLOTS {
+                    self.memory_written
+                        .write()
+                        .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?
+                        .insert(index);
                 }
-                let mut memory = self
-                    .memory
-                    .write()
-                    .map_err(|e| InterpreterError::LockPoisoned(format!("{e}")))?;
-
-                memory[index] = value;
-                Ok(())
+                self.write_memory(index, value)
             }
             Err(e) => Err(InterpreterError::InvalidMemoryAddress(format!(
                 "Invalid address: {address:?} - {e}"
             ))),
         }
     }
-    fn get_register<T: AsRef<str> + Display>(
+    pub(crate) fn get_register<T: AsRef<str> + Display>(
         &self,
         register: T,
     ) -> Result<Value, InterpreterError> {
@@ -701,7 +1752,7 @@ impl Interpreter {
             }
         }
     }
-    fn set_register<T: AsRef<str>>(
+    pub(crate) fn set_register<T: AsRef<str>>(
         &self,
         register: T,
         value: Value,
@@ -721,19 +1772,276 @@ impl Interpreter {
     }
 }
 
-fn resolve_operand_compile(operand: &Operand, constants: &HashMap<String, Value>) -> Option<Value> {
+/// Turn the error from an operation on `left` and `right` into an `InterpreterError`
+fn value_error(error: ValueError) -> InterpreterError {
+    match error {
+        ValueError::DivisionByZero(a, b) => InterpreterError::DivisionByZero(a, b),
+        ValueError::Overflow(message) => InterpreterError::Overflow(message),
+        ValueError::InvalidOperation(message) => InterpreterError::Other(message),
+        ValueError::TypeMismatch(left, right) => {
+            InterpreterError::TypeMismatch(Box::new((left, right)))
+        }
+        error @ ValueError::ConversionError(_) => InterpreterError::Other(error.to_string()),
+    }
+}
+
+fn poisoned<E: Display>(e: E) -> InterpreterError {
+    InterpreterError::LockPoisoned(format!("{e}"))
+}
+
+/// The constants that can be used at some point while a program is being compiled
+struct ConstantScope<'a> {
+    /// Every constant defined so far
+    defined: &'a HashMap<String, Value>,
+    /// Where each constant in the program is first defined
+    definitions: &'a HashMap<String, Span>,
+    /// The constants whose definitions have already been reported as wrong
+    failed: &'a HashSet<String>,
+}
+
+impl ConstantScope<'_> {
+    /// The value of a constant
+    /// # Errors
+    /// Returns an error saying whether the constant isn't defined anywhere, is only defined
+    /// later on, or couldn't be worked out
+    fn get(&self, name: &str) -> Result<Value, String> {
+        if let Some(value) = self.defined.get(name) {
+            return Ok(value.clone());
+        }
+        if self.failed.contains(name) {
+            return Err(format!("The constant {name} couldn't be worked out"));
+        }
+        Err(match self.definitions.get(name) {
+            Some(span) => format!("The constant {name} is used before it is defined on {span}"),
+            None => format!("The constant {name} is not defined"),
+        })
+    }
+}
+
+/// Find room in RAM for the `VAR` called `name`, which is `size` slots long, after the slots
+/// that have already been handed out. The value of its constant is the address of the first slot
+/// # Errors
+/// Returns an error when the size isn't a positive number, or there isn't enough RAM left
+fn allocate(
+    name: &str,
+    size: Option<&Operand>,
+    next_free_slot: &mut usize,
+    scope: &ConstantScope,
+) -> Result<Value, String> {
+    let slots = match size {
+        Some(size) => match constant_value(size, scope)? {
+            Value::Number(n) => usize::try_from(n).ok().filter(|slots| *slots > 0),
+            Value::String(_) => None,
+        }
+        .ok_or_else(|| format!("VAR {name} needs a size that's a positive number, not {size}"))?,
+        None => 1,
+    };
+    let free = RAM_SLOTS - *next_free_slot;
+    if slots > free {
+        return Err(format!(
+            "There isn't enough memory for {name}, which needs {slots} slots when only {free} \
+             are left"
+        ));
+    }
+    let address = i64::try_from(*next_free_slot).map_err(|e| e.to_string())?;
+    *next_free_slot += slots;
+    Ok(Value::Number(address))
+}
+
+/// Values to put in memory one after another, as runs of the same value so a large `FILL` doesn't
+/// need a value for every slot
+type DataRuns = Vec<(Value, usize)>;
+
+/// Where a `DATA`, `FILL` or `STRING` directive puts its values, and the values
+/// # Errors
+/// Returns an error when an operand can't be worked out, the address isn't a memory address,
+/// `FILL` isn't given a count that's a positive number, or `STRING` isn't given a string
+fn data_runs(directive: &Instruction, scope: &ConstantScope) -> Result<(usize, DataRuns), String> {
+    let (dest, runs) = match directive {
+        Instruction::Data { dest, values } => {
+            let runs = values
+                .iter()
+                .map(|value| constant_value(value, scope).map(|value| (value, 1)))
+                .collect::<Result<_, _>>()?;
+            (dest, runs)
+        }
+        Instruction::DataFill { dest, count, value } => {
+            let count = match constant_value(count, scope)? {
+                Value::Number(n) => usize::try_from(n).ok().filter(|count| *count > 0),
+                Value::String(_) => None,
+            }
+            .ok_or_else(|| format!("FILL needs a count that's a positive number, not {count}"))?;
+            (dest, vec![(constant_value(value, scope)?, count)])
+        }
+        Instruction::DataString { dest, text } => match constant_value(text, scope)? {
+            Value::String(text) => {
+                let runs = text
+                    .chars()
+                    .map(|c| (Value::String(c.to_string()), 1))
+                    .collect();
+                (dest, runs)
+            }
+            Value::Number(n) => return Err(format!("STRING needs a string, not the number {n}")),
+        },
+        directive => return Err(format!("{} isn't a data directive", directive.mnemonic())),
+    };
+    let mut dest = dest.clone();
+    fold_operand(&mut dest, scope)?;
+    let address = match &dest {
+        Operand::Memory(address) => convert_string_to_num(address.trim_start_matches('%'))
+            .ok()
+            .and_then(|address| usize::try_from(address).ok()),
+        _ => None,
+    }
+    .ok_or_else(|| format!("{dest} is not a memory address"))?;
+    Ok((address, runs))
+}
+
+/// The label a `JMP` or `CALL` goes to, if it goes to one
+fn label_target(statement: &mut Statement) -> Option<&mut String> {
+    match statement {
+        Statement::Instruction(
+            Instruction::Jmp {
+                target: Operand::Identifier(target),
+                ..
+            }
+            | Instruction::Call {
+                target: Operand::Identifier(target),
+            },
+        ) => Some(target),
+        _ => None,
+    }
+}
+
+/// The instruction a statement adds to the program. Data directives count as instructions, so
+/// a level's restrictions and instruction limit can't be got around by working out the answer
+/// ahead of time and putting it in memory
+fn program_instruction(statement: &Statement) -> Option<&Instruction> {
+    match statement {
+        Statement::Instruction(instruction)
+        | Statement::CompileTime(
+            instruction @ (Instruction::Data { .. }
+            | Instruction::DataFill { .. }
+            | Instruction::DataString { .. }),
+        ) => Some(instruction),
+        Statement::Label(_) | Statement::CompileTime(_) => None,
+    }
+}
+
+/// Make sure `span` is where the constant `name` is first defined, so it isn't defined twice
+fn first_definition(
+    name: &str,
+    span: &Span,
+    definitions: &HashMap<String, Span>,
+) -> Result<(), String> {
+    match definitions.get(name) {
+        Some(first) if first != span => {
+            Err(format!("The constant {name} is already defined on {first}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The file and macro call an anonymous label can be jumped to from
+type AnonymousScope = (Option<Arc<str>>, Option<Arc<Expansion>>);
+
+/// Why an anonymous label like `+` or `--` doesn't go anywhere
+fn missing_anonymous_label(target: &str) -> String {
+    let (sign, direction) = if target.starts_with('+') {
+        ('+', "after")
+    } else {
+        ('-', "before")
+    };
+    match target.len() {
+        1 => format!("There is no {sign}: label {direction} this"),
+        count => format!("There aren't {count} {sign}: labels {direction} this"),
+    }
+}
+
+/// How a label is written in diagnostics, giving a local label like `first@loop` as it was
+/// written along with the label it belongs to
+fn label_name(label: &str) -> String {
+    match label.split_once('@') {
+        Some((scope, local)) if !scope.is_empty() => format!("@{local} in {scope}"),
+        _ => label.to_string(),
+    }
+}
+
+/// The value of an operand in a `DEFINE` or a constant expression
+/// # Errors
+/// Returns an error when the operand uses a constant that isn't defined, or can't be worked out
+/// when compiling, like a register
+fn constant_value(operand: &Operand, scope: &ConstantScope) -> Result<Value, String> {
     match operand {
-        Operand::Number(num) => convert_string_to_num(num).ok().map(Value::Number),
-        Operand::Constant(name) => constants.get(name).cloned(),
-        Operand::Character(c) => Some(Value::String(c.clone())),
-        Operand::String(s) => Some(Value::String(s.clone())),
+        Operand::Number(num) => convert_string_to_num(num)
+            .map(Value::Number)
+            .map_err(|e| format!("{num} is not a valid number: {e}")),
+        Operand::Constant(name) => scope.get(name),
+        Operand::Character(s) | Operand::String(s) => Ok(Value::String(s.clone())),
+        Operand::Expression(expression) => evaluate(expression, scope),
         Operand::Register(_)
         | Operand::Memory(_)
         | Operand::Identifier(_)
-        | Operand::IndirectMemory(_) => None,
+        | Operand::IndirectMemory(_)
+        | Operand::MemoryExpression(_) => Err(format!("{operand} is not a constant")),
+    }
+}
+
+/// Work out the value of a constant expression
+/// # Errors
+/// Returns an error when the expression uses a constant that isn't defined, mixes strings and
+/// numbers in a way that doesn't work, or the arithmetic fails
+fn evaluate(expression: &Expression, scope: &ConstantScope) -> Result<Value, String> {
+    match expression {
+        Expression::Operand(operand) => constant_value(operand, scope),
+        Expression::Negate(inner) => evaluate(inner, scope)?
+            .neg()
+            .map_err(|e| format!("Can't work out {expression}: {e}")),
+        Expression::Binary { op, left, right } => {
+            let left = evaluate(left, scope)?;
+            let right = evaluate(right, scope)?;
+            match op {
+                ExpressionOp::Add => left.add(&right),
+                ExpressionOp::Sub => left.sub(&right),
+                ExpressionOp::Mul => left.mul(&right),
+                ExpressionOp::Div => left.div(&right),
+                ExpressionOp::Mod => left.rem(&right),
+            }
+            .map_err(|e| format!("Can't work out {expression}: {e}"))
+        }
     }
 }
 
+/// Replace an operand made from a constant expression with the plain operand it works out to, and
+/// make sure any constant it names is defined
+/// # Errors
+/// Returns an error when a constant isn't defined yet, the expression can't be worked out, or a
+/// memory address is negative or not a number
+fn fold_operand(operand: &mut Operand, scope: &ConstantScope) -> Result<(), String> {
+    match operand {
+        Operand::Constant(name) => _ = scope.get(name)?,
+        Operand::Expression(expression) => {
+            *operand = match evaluate(expression, scope)? {
+                Value::Number(n) => Operand::Number(n.to_string()),
+                Value::String(s) => Operand::String(s),
+            };
+        }
+        Operand::MemoryExpression(expression) => {
+            let address = match evaluate(expression, scope)? {
+                Value::Number(n) if n >= 0 => n,
+                value => {
+                    return Err(format!(
+                        "The memory address %({expression}) is {value:?}, not a non-negative number"
+                    ));
+                }
+            };
+            *operand = Operand::Memory(format!("%{address}"));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
 fn convert_string_to_num<T: AsRef<str>>(input: T) -> Result<i64, ParseIntError> {
     let input = input.as_ref().to_lowercase().trim().to_string();
     if let Some(hex) = input.strip_prefix("0x") {
@@ -749,6 +2057,10 @@ fn convert_string_to_num<T: AsRef<str>>(input: T) -> Result<i64, ParseIntError>
 mod tests {
     use super::*;
     use crate::ast;
+    use crate::console::SharedBuffer;
+    use crate::source::MemoryLoader;
+    use crate::value::{TYPE_NUMBER, TYPE_STRING};
+    use std::io::Cursor;
 
     fn set_reg(interpreter: &mut Interpreter, name: &str, value: Value) {
         interpreter
@@ -789,6 +2101,75 @@ mod tests {
             .execute_clear(&ast::Operand::Memory(addr.to_string()))
             .unwrap();
     }
+
+    fn run_program(source: &str) -> Result<Interpreter, InterpreterError> {
+        run_program_with(&[], source)
+    }
+
+    /// The line and message of every diagnostic a program that shouldn't compile gives
+    fn compile_errors(source: &str) -> Vec<(usize, String)> {
+        match run_program(source) {
+            Err(InterpreterError::Compile(diagnostics)) => diagnostics
+                .into_iter()
+                .map(|diagnostic| (diagnostic.span.line, diagnostic.message))
+                .collect(),
+            Err(error) => panic!("{source} should fail to compile, not give {error}"),
+            Ok(_) => panic!("{source} should fail to compile"),
+        }
+    }
+
+    /// Check the line and message of every diagnostic a program that shouldn't compile gives
+    fn assert_compile_errors(source: &str, expected: &[(usize, &str)]) {
+        let errors = compile_errors(source);
+        let found: Vec<(usize, &str)> = errors
+            .iter()
+            .map(|(line, message)| (*line, message.as_str()))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    /// Run a program with some registers already holding values
+    fn run_program_with(
+        registers: &[(&str, Value)],
+        source: &str,
+    ) -> Result<Interpreter, InterpreterError> {
+        let mut interpreter = Interpreter::new();
+        for (register, value) in registers {
+            interpreter.set_register(register, value.clone())?;
+        }
+        interpreter.parse(source)?;
+        while interpreter.running.load(Ordering::SeqCst) {
+            interpreter.step()?;
+        }
+        Ok(interpreter)
+    }
+
+    /// Run one instruction with `mode` arithmetic on `width` bit words, giving back the
+    /// accumulator and whether the overflow flag is set
+    fn run_in_mode(
+        mode: ArithmeticMode,
+        width: u32,
+        instruction: &str,
+    ) -> Result<(Value, bool), InterpreterError> {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_arithmetic_mode(mode);
+        interpreter.set_word_width(width).unwrap();
+        interpreter.parse(format!("{instruction}\nHALT\n")).unwrap();
+        while interpreter.running.load(Ordering::SeqCst) {
+            interpreter.step()?;
+        }
+        let Value::Number(flags) = interpreter.get_register("f").unwrap() else {
+            panic!("F should be a number");
+        };
+        Ok((
+            interpreter.get_register("a").unwrap(),
+            flags & FLAG_OVERFLOW == FLAG_OVERFLOW,
+        ))
+    }
+
+    fn get_pixel(interpreter: &Interpreter, x: usize, y: usize) -> (i32, i32, i32) {
+        interpreter.display.read().unwrap()[y * DISPLAY_WIDTH + x]
+    }
     #[test]
     fn test_convert_string_to_num() {
         let cases = vec![
@@ -1046,4 +2427,1348 @@ mod tests {
             "Expected error when using OR on a number and a string"
         );
     }
+    #[test]
+    fn test_pixel_and_fill() {
+        let interpreter = run_program(
+            "FILL 0x000080\nSET R0, 3\nPIXEL R0, 4, 0xFF8000\nPIXEL 15, 15, 0xFFFFFF\nHALT\n",
+        )
+        .unwrap();
+        assert_eq!(get_pixel(&interpreter, 3, 4), (255, 128, 0));
+        assert_eq!(get_pixel(&interpreter, 15, 15), (255, 255, 255));
+        assert_eq!(get_pixel(&interpreter, 0, 0), (0, 0, 128));
+    }
+    #[test]
+    fn test_line_and_rect() {
+        let interpreter =
+            run_program("LINE 0, 0, 3, 3, 0xFF0000\nRECT 14, 0, 5, 2, 0x00FF00\n").unwrap();
+        for i in 0..4 {
+            assert_eq!(get_pixel(&interpreter, i, i), (255, 0, 0));
+        }
+        assert_eq!(get_pixel(&interpreter, 1, 0), (0, 0, 0));
+        for (x, y) in [(14, 0), (15, 0), (14, 1), (15, 1)] {
+            assert_eq!(get_pixel(&interpreter, x, y), (0, 255, 0));
+        }
+        assert_eq!(get_pixel(&interpreter, 14, 2), (0, 0, 0));
+    }
+    #[test]
+    fn test_invalid_pixels_and_colours() {
+        assert!(matches!(
+            run_program("PIXEL 16, 0, 0xFFFFFF\n"),
+            Err(InterpreterError::InvalidPixel(16, 0))
+        ));
+        assert!(matches!(
+            run_program("FILL 0x1000000\n"),
+            Err(InterpreterError::InvalidColour(0x100_0000))
+        ));
+        assert!(matches!(
+            run_program("LINE 0, 0, 5, -1, 0\n"),
+            Err(InterpreterError::InvalidPixel(5, -1))
+        ));
+        assert!(matches!(
+            run_program("PIXEL 0, 0, -1\n"),
+            Err(InterpreterError::InvalidColour(-1))
+        ));
+    }
+    #[test]
+    fn test_flip_signals_frames() {
+        let interpreter = run_program("PIXEL 0, 0, 0xFF\nFLIP\nSYNC\n").unwrap();
+        assert_eq!(interpreter.frames.load(Ordering::SeqCst), 2);
+        assert!(interpreter.frame_ready.load(Ordering::SeqCst));
+    }
+    #[test]
+    fn test_on_frame_callback() {
+        let frames = Arc::new(RwLock::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        let seen = Arc::clone(&frames);
+        interpreter.on_frame(move |frame, display| {
+            seen.write().unwrap().push((frame, display[0]));
+        });
+        interpreter
+            .parse("PIXEL 0, 0, 0xFF\nFLIP\nPIXEL 0, 0, 0xFF00\nFLIP\nHALT\n")
+            .unwrap();
+        while interpreter.running.load(Ordering::SeqCst) {
+            interpreter.step().unwrap();
+        }
+        assert_eq!(
+            *frames.read().unwrap(),
+            vec![(1, (0, 0, 255)), (2, (0, 255, 0))]
+        );
+    }
+    #[test]
+    fn test_memory_mapped_display() {
+        let interpreter = run_program(
+            "SET %256, 0xFF0000\n\
+             SET R2, 0x00FF00\n\
+             STORE R2, %0x111\n\
+             MOV %256, %0x1FF\n\
+             LOAD %0x111, R3\n",
+        )
+        .unwrap();
+        assert_eq!(get_pixel(&interpreter, 0, 0), (255, 0, 0));
+        assert_eq!(get_pixel(&interpreter, 1, 1), (0, 255, 0));
+        assert_eq!(get_pixel(&interpreter, 15, 15), (255, 0, 0));
+        assert_eq!(get_reg(&interpreter, "r3"), Some(Value::Number(0xFF00)));
+
+        assert!(matches!(
+            run_program("SET %256, 0x1000000\n"),
+            Err(InterpreterError::InvalidColour(0x100_0000))
+        ));
+        assert!(matches!(
+            run_program("SET %0x200, 1\n"),
+            Err(InterpreterError::InvalidMemoryAddress(_))
+        ));
+    }
+    #[test]
+    fn test_map_custom_memory_region() {
+        struct Doubler;
+        impl MemoryRegion for Doubler {
+            fn size(&self) -> usize {
+                4
+            }
+            fn read(&self, offset: usize) -> Result<Value, InterpreterError> {
+                Ok(Value::Number(i64::try_from(offset).unwrap() * 2))
+            }
+            fn write(&self, _offset: usize, value: Value) -> Result<(), InterpreterError> {
+                Err(InterpreterError::Other(format!(
+                    "Read only region: {value}"
+                )))
+            }
+        }
+
+        let mut interpreter = Interpreter::new();
+        assert!(interpreter.map_memory(100, Doubler).is_err());
+        assert!(
+            interpreter
+                .map_memory(DISPLAY_ADDRESS + 10, Doubler)
+                .is_err()
+        );
+        interpreter.map_memory(0x400, Doubler).unwrap();
+        interpreter.parse("LOAD %0x403, R1\n").unwrap();
+        interpreter.step().unwrap();
+        assert_eq!(get_reg(&interpreter, "r1"), Some(Value::Number(6)));
+        assert!(
+            interpreter
+                .execute_set(Value::Number(1), &Operand::Memory("%0x400".to_string()))
+                .is_err()
+        );
+    }
+    #[test]
+    fn test_in_and_out_ports() {
+        let mut interpreter = Interpreter::new();
+        for n in [3, 4, 5] {
+            interpreter.push_input(0, Value::Number(n)).unwrap();
+        }
+        interpreter.push_input(1, Value::Number(10)).unwrap();
+        interpreter
+            .parse("LOOP:\nIN 0, R0\nIN 1, R1\nADD R0, R1\nOUT A, 0\nOUT \"done\", 3\nJMP LOOP\n")
+            .unwrap();
+        interpreter.run();
+
+        assert!(interpreter.waiting.load(Ordering::SeqCst));
+        assert_eq!(interpreter.take_output(0).unwrap(), vec![Value::Number(13)]);
+        assert_eq!(interpreter.take_output(3).unwrap().len(), 1);
+        assert_eq!(interpreter.inputs.read().unwrap()[0].len(), 1);
+        assert!(interpreter.take_output(0).unwrap().is_empty());
+    }
+    #[test]
+    fn test_in_blocks_until_input_arrives() {
+        let mut interpreter = Interpreter::new();
+        interpreter.parse("IN 2, %5\nHALT\n").unwrap();
+        interpreter.step().unwrap();
+        interpreter.step().unwrap();
+        assert!(interpreter.waiting.load(Ordering::SeqCst));
+        assert_eq!(interpreter.pc.load(Ordering::SeqCst), 0);
+
+        interpreter
+            .push_input(2, Value::String("hi".to_string()))
+            .unwrap();
+        interpreter.step().unwrap();
+        assert!(!interpreter.waiting.load(Ordering::SeqCst));
+        assert_eq!(interpreter.pc.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            get_mem(&interpreter, 5),
+            Some(Value::String("hi".to_string()))
+        );
+    }
+    #[test]
+    fn test_invalid_ports() {
+        let interpreter = Interpreter::new();
+        assert!(interpreter.push_input(PORTS, Value::Number(1)).is_err());
+        assert!(matches!(
+            run_program("OUT 1, 4\n"),
+            Err(InterpreterError::InvalidPort(_))
+        ));
+        assert!(matches!(
+            run_program("IN -1, R0\n"),
+            Err(InterpreterError::InvalidPort(_))
+        ));
+    }
+    #[test]
+    fn test_print_and_read() {
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(output.clone());
+        interpreter.set_input(Cursor::new(" 0x10 \nhello there \r\nabc\n"));
+        interpreter
+            .parse("READ R0\nREADLINE R1\nREAD R2\nPRINT R0\nPRINT 2\nPRINTLN R1\nPRINTLN R2\n")
+            .unwrap();
+        while interpreter.running.load(Ordering::SeqCst) {
+            interpreter.step().unwrap();
+        }
+
+        assert_eq!(get_reg(&interpreter, "r0"), Some(Value::Number(16)));
+        assert_eq!(
+            get_reg(&interpreter, "r1"),
+            Some(Value::String("hello there ".to_string()))
+        );
+        assert_eq!(
+            get_reg(&interpreter, "r2"),
+            Some(Value::String("abc".to_string()))
+        );
+        assert_eq!(output.contents(), "162hello there \nabc\n");
+    }
+    #[test]
+    fn test_read_past_end_of_input() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_input(Cursor::new("1\n"));
+        interpreter.parse("READ R0\nREADLINE R1\n").unwrap();
+        interpreter.step().unwrap();
+        assert!(matches!(
+            interpreter.step(),
+            Err(InterpreterError::EndOfInput)
+        ));
+    }
+    #[test]
+    fn test_execution_counters() {
+        let interpreter = run_program(
+            "SET %1, 5\nSET %1, 6\nSET %200, 1\nSET %256, 1\nPUSH 1\nPUSH 2\nPOP\nPUSH 3\nHALT\n",
+        )
+        .unwrap();
+        assert_eq!(interpreter.cycles.load(Ordering::SeqCst), 9);
+        assert_eq!(interpreter.memory_slots_used().unwrap(), 2);
+        assert_eq!(interpreter.peak_stack_depth.load(Ordering::SeqCst), 2);
+    }
+    #[test]
+    fn test_restrictions_are_checked_at_parse_time() {
+        let mut interpreter = Interpreter::new();
+        interpreter.restrict(
+            Restrictions::new()
+                .allow_instructions(["SET", "ADD", "JMP"])
+                .allow_registers(["R0"]),
+        );
+        let result =
+            interpreter.parse("START:\n    SET R0, 1\n    MUL R0, R1\n    JMP START A > 1\n");
+        let Err(InterpreterError::Compile(diagnostics)) = result else {
+            panic!("Expected compile errors, got {result:?}");
+        };
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic {
+                    span: Span::new(3, 5),
+                    message: "MUL is not available in this level".to_string(),
+                },
+                Diagnostic {
+                    span: Span::new(3, 5),
+                    message: "Register R1 is not available in this level".to_string(),
+                },
+            ]
+        );
+        assert!(interpreter.parse("SET R0, 1\nADD R0, A\n").is_ok());
+    }
+    #[test]
+    fn test_rand_is_deterministic() {
+        let program = "LOOP:\nRAND R1, 1, 6\nPUSH R1\nINC R0\nJMP LOOP R0 < 10\nHALT\n";
+        let rolls = |seed| {
+            let mut interpreter = Interpreter::new();
+            interpreter.seed(seed);
+            interpreter.parse(program).unwrap();
+            interpreter.run();
+            interpreter.stack.read().unwrap().clone()
+        };
+        let first = rolls(7);
+        assert_eq!(first.len(), 10);
+        assert_eq!(first, rolls(7));
+        assert_ne!(first, rolls(8));
+        for roll in first {
+            let Value::Number(n) = roll else {
+                panic!("Expected a number, got {roll:?}");
+            };
+            assert!((1..=6).contains(&n));
+        }
+        assert!(matches!(
+            run_program("RAND R0, 5, 1\n"),
+            Err(InterpreterError::InvalidOperand(_))
+        ));
+    }
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut interpreter = Interpreter::new();
+        interpreter.seed(99);
+        interpreter
+            .parse("SET R0, 1\nRAND R1, 0, 1000000\nPUSH R1\nSET %3, 4\nPIXEL 0, 0, 0xFF\nHALT\n")
+            .unwrap();
+        interpreter.step().unwrap();
+        let snapshot = interpreter.snapshot().unwrap();
+        assert_eq!(snapshot.pc(), 1);
+        interpreter.run();
+        let roll = interpreter.get_register("r1").unwrap();
+        let finished = interpreter.snapshot().unwrap();
+
+        interpreter.restore(&snapshot).unwrap();
+        assert_eq!(interpreter.snapshot().unwrap(), snapshot);
+        assert_eq!(interpreter.read_memory(3).unwrap(), Value::Number(0));
+        assert!(interpreter.running.load(Ordering::SeqCst));
+        interpreter.run();
+        assert_eq!(interpreter.get_register("r1").unwrap(), roll);
+        assert_eq!(interpreter.snapshot().unwrap(), finished);
+
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .parse(
+                "FLIP
+HALT
+",
+            )
+            .unwrap();
+        let before_flip = interpreter.snapshot().unwrap();
+        interpreter.step().unwrap();
+        assert!(interpreter.frame_ready.load(Ordering::SeqCst));
+        interpreter.restore(&before_flip).unwrap();
+        assert!(!interpreter.frame_ready.load(Ordering::SeqCst));
+    }
+    #[test]
+    fn test_mod_is_euclidean() {
+        for (a, b, expected) in [(7, 3, 1), (-7, 3, 2), (7, -3, 1), (-7, -3, 2), (6, 3, 0)] {
+            let interpreter = run_program(&format!("MOD {a}, {b}\nHALT\n")).unwrap();
+            assert_eq!(
+                interpreter.get_register("a").unwrap(),
+                Value::Number(expected),
+                "{a} MOD {b}"
+            );
+        }
+        assert!(matches!(
+            run_program("MOD 7, 0\n"),
+            Err(InterpreterError::DivisionByZero(7, 0))
+        ));
+    }
+    #[test]
+    fn test_divmod_truncates() {
+        for (a, b, quotient, remainder) in [
+            (7, 3, 2, 1),
+            (-7, 3, -2, -1),
+            (7, -3, -2, 1),
+            (-7, -3, 2, -1),
+        ] {
+            let interpreter = run_program(&format!("DIVMOD {a}, {b}, R1\nHALT\n")).unwrap();
+            assert_eq!(
+                interpreter.get_register("a").unwrap(),
+                Value::Number(quotient),
+                "{a} DIVMOD {b}"
+            );
+            assert_eq!(
+                interpreter.get_register("r1").unwrap(),
+                Value::Number(remainder),
+                "{a} DIVMOD {b}"
+            );
+        }
+        let interpreter = run_program("DIVMOD 17, 5, %4\nHALT\n").unwrap();
+        assert_eq!(interpreter.read_memory(4).unwrap(), Value::Number(2));
+        assert!(matches!(
+            run_program("DIVMOD 1, 0, R1\n"),
+            Err(InterpreterError::DivisionByZero(1, 0))
+        ));
+        assert!(run_program(&format!("DIVMOD {}, -1, R1\n", i64::MIN)).is_err());
+    }
+    #[test]
+    fn test_neg_abs_min_max() {
+        let cases = [
+            ("NEG 5", Value::Number(-5)),
+            ("NEG -5", Value::Number(5)),
+            ("ABS -12", Value::Number(12)),
+            ("ABS 12", Value::Number(12)),
+            ("MIN 3, -4", Value::Number(-4)),
+            ("MAX 3, -4", Value::Number(3)),
+            ("MIN \"abc\", \"abd\"", Value::String("abc".to_string())),
+        ];
+        for (instruction, expected) in cases {
+            let interpreter = run_program(&format!("{instruction}\nHALT\n")).unwrap();
+            assert_eq!(
+                interpreter.get_register("a").unwrap(),
+                expected,
+                "{instruction}"
+            );
+        }
+        assert!(run_program(&format!("ABS {}\n", i64::MIN)).is_err());
+        assert!(run_program("NEG \"abc\"\n").is_err());
+        assert!(run_program("MAX 1, \"abc\"\n").is_err());
+    }
+    #[test]
+    fn test_shifts_and_rotates() {
+        let shift = |width, instruction: &str| {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_word_width(width).unwrap();
+            interpreter.parse(format!("{instruction}\nHALT\n")).unwrap();
+            interpreter.run();
+            let Value::Number(flags) = interpreter.get_register("f").unwrap() else {
+                panic!("F should be a number");
+            };
+            (
+                interpreter.get_register("a").unwrap(),
+                flags & FLAG_CARRY == FLAG_CARRY,
+            )
+        };
+        let cases = [
+            (64, "SHL 3, 2", 12, false),
+            (64, "SHL -1, 1", -2, true),
+            (64, "SHR -8, 1", -4, false),
+            (64, "SHR -7, 1", -4, true),
+            (64, "LSR -1, 60", 15, true),
+            (64, "ROL -1, 5", -1, true),
+            (64, "ROR 1, 1", i64::MIN, true),
+            (8, "SHL 0x81, 1", 2, true),
+            (8, "SHL 0x40, 1", -128, false),
+            (8, "SHR 0x80, 7", -1, false),
+            (8, "SHR 0x80, 20", -1, true),
+            (8, "LSR 0x80, 7", 1, false),
+            (8, "LSR 0xFF, 9", 0, false),
+            (8, "ROL 0x81, 1", 3, true),
+            (8, "ROR 0x81, 1", -64, true),
+            (8, "ROL 0x12, 12", 0x21, true),
+            (16, "SHL 0x5555, 0", 0x5555, false),
+        ];
+        for (width, instruction, expected, carry) in cases {
+            assert_eq!(
+                shift(width, instruction),
+                (Value::Number(expected), carry),
+                "{instruction} at {width} bits"
+            );
+        }
+        assert!(run_program("SHL 1, -1\n").is_err());
+        assert!(Interpreter::new().set_word_width(65).is_err());
+    }
+    #[test]
+    fn test_shift_keeps_other_flags() {
+        let interpreter = run_program("SET F, 6\nSHL 1, 64\nHALT\n").unwrap();
+        assert_eq!(interpreter.get_register("f").unwrap(), Value::Number(7));
+        let interpreter = run_program("SET F, 7\nSHL 1, 1\nHALT\n").unwrap();
+        assert_eq!(interpreter.get_register("f").unwrap(), Value::Number(6));
+    }
+    #[test]
+    fn test_arithmetic_modes() {
+        let max = i64::MAX;
+        let min = i64::MIN;
+        let cases = [
+            (ArithmeticMode::Wrapping, 64, format!("ADD {max}, 1"), min),
+            (ArithmeticMode::Saturating, 64, format!("ADD {max}, 1"), max),
+            (ArithmeticMode::Wrapping, 64, format!("MUL {max}, 2"), -2),
+            (
+                ArithmeticMode::Saturating,
+                64,
+                format!("MUL {max}, -2"),
+                min,
+            ),
+            (ArithmeticMode::Wrapping, 64, format!("NEG {min}"), min),
+            (ArithmeticMode::Saturating, 64, format!("ABS {min}"), max),
+            (ArithmeticMode::Wrapping, 8, "ADD 127, 1".to_string(), -128),
+            (ArithmeticMode::Saturating, 8, "ADD 127, 1".to_string(), 127),
+            (ArithmeticMode::Wrapping, 8, "SUB -128, 1".to_string(), 127),
+            (
+                ArithmeticMode::Saturating,
+                8,
+                "SUB -128, 1".to_string(),
+                -128,
+            ),
+            (
+                ArithmeticMode::Wrapping,
+                16,
+                "MUL 300, 300".to_string(),
+                24464,
+            ),
+            (
+                ArithmeticMode::Saturating,
+                16,
+                "MUL 300, 300".to_string(),
+                32767,
+            ),
+            (
+                ArithmeticMode::Wrapping,
+                8,
+                "SET R0, 127\nINC R0\nMOV R0, A".to_string(),
+                -128,
+            ),
+        ];
+        for (mode, width, instruction, expected) in cases {
+            assert_eq!(
+                run_in_mode(mode, width, &instruction).unwrap(),
+                (Value::Number(expected), true),
+                "{instruction} in {mode:?} at {width} bits"
+            );
+        }
+        assert_eq!(
+            run_in_mode(ArithmeticMode::Wrapping, 8, "ADD 100, 27").unwrap(),
+            (Value::Number(127), false)
+        );
+        assert!(matches!(
+            run_in_mode(ArithmeticMode::Checked, 64, &format!("ADD {max}, 1")),
+            Err(InterpreterError::Overflow(_))
+        ));
+        assert!(matches!(
+            run_in_mode(ArithmeticMode::Checked, 8, "DEC -128"),
+            Err(InterpreterError::Overflow(_))
+        ));
+        assert!(matches!(
+            run_program(&format!("MUL {max}, {max}\n")),
+            Err(InterpreterError::Overflow(_))
+        ));
+    }
+    #[test]
+    fn test_division_arithmetic_modes() {
+        let min = i64::MIN;
+        let cases = [
+            (
+                ArithmeticMode::Wrapping,
+                8,
+                "DIV -128, -1".to_string(),
+                -128,
+            ),
+            (
+                ArithmeticMode::Wrapping,
+                8,
+                "DIVMOD -128, -1, R1".to_string(),
+                -128,
+            ),
+            (
+                ArithmeticMode::Saturating,
+                8,
+                "DIVMOD -128, -1, R1".to_string(),
+                127,
+            ),
+            (
+                ArithmeticMode::Wrapping,
+                64,
+                format!("DIVMOD {min}, -1, R1"),
+                min,
+            ),
+        ];
+        for (mode, width, instruction, expected) in cases {
+            assert_eq!(
+                run_in_mode(mode, width, &instruction).unwrap(),
+                (Value::Number(expected), true),
+                "{instruction} in {mode:?} at {width} bits"
+            );
+        }
+        assert_eq!(
+            run_in_mode(ArithmeticMode::Wrapping, 8, "MOD -128, -1").unwrap(),
+            (Value::Number(0), false)
+        );
+    }
+    #[test]
+    fn test_division_sign_matrix() {
+        // (dividend, divisor, truncated, floored)
+        let cases = [
+            (7, 2, 3, 3),
+            (-7, 2, -3, -4),
+            (7, -2, -3, -4),
+            (-7, -2, 3, 3),
+            (6, 2, 3, 3),
+            (-6, 2, -3, -3),
+            (6, -2, -3, -3),
+            (-6, -2, 3, 3),
+            (0, -5, 0, 0),
+            (1, -5, 0, -1),
+            (10, -2, -5, -5),
+        ];
+        for (a, b, truncated, floored) in cases {
+            let interpreter = run_program(&format!("DIV {a}, {b}\nHALT\n")).unwrap();
+            assert_eq!(
+                interpreter.get_register("a").unwrap(),
+                Value::Number(truncated),
+                "{a} DIV {b}"
+            );
+            let interpreter = run_program(&format!("FDIV {a}, {b}\nHALT\n")).unwrap();
+            assert_eq!(
+                interpreter.get_register("a").unwrap(),
+                Value::Number(floored),
+                "{a} FDIV {b}"
+            );
+        }
+        for instruction in ["DIV", "FDIV"] {
+            assert!(matches!(
+                run_program(&format!("{instruction} 5, 0\n")),
+                Err(InterpreterError::DivisionByZero(5, 0))
+            ));
+            assert!(matches!(
+                run_program(&format!("{instruction} {}, -1\n", i64::MIN)),
+                Err(InterpreterError::Overflow(_))
+            ));
+        }
+        let mut interpreter = Interpreter::new();
+        interpreter.set_arithmetic_mode(ArithmeticMode::Wrapping);
+        interpreter
+            .parse(format!("DIV {}, -1\nHALT\n", i64::MIN))
+            .unwrap();
+        interpreter.run();
+        assert_eq!(
+            interpreter.get_register("a").unwrap(),
+            Value::Number(i64::MIN)
+        );
+        assert_eq!(
+            interpreter.get_register("f").unwrap(),
+            Value::Number(FLAG_OVERFLOW)
+        );
+    }
+    #[test]
+    fn test_string_instructions() {
+        let string = |s: &str| Value::String(s.to_string());
+        let run = |text: &str, instruction: &str| {
+            let registers = [("r0", string(text)), ("r1", string(","))];
+            run_program_with(&registers, &format!("{instruction}\nHALT\n"))
+                .map(|interpreter| interpreter.get_register("a").unwrap())
+        };
+        let cases = [
+            ("héllo", "LEN R0", Value::Number(5)),
+            ("🦀🦀", "LEN R0", Value::Number(2)),
+            ("héllo", "CHARAT R0, 1", string("é")),
+            ("héllo", "CHARAT R0, -1", string("o")),
+            ("héllo", "SUBSTR R0, 1, 3", string("éll")),
+            ("héllo", "SUBSTR R0, -2, 2", string("lo")),
+            ("héllo", "SUBSTR R0, 3, 10", string("lo")),
+            ("a,b,c", "FIND R0, R1", Value::Number(1)),
+            ("héllo", "FIND R0, R1", Value::Number(-1)),
+            ("héllo", "UPPER R0", string("HÉLLO")),
+            ("HÉLLO", "LOWER R0", string("héllo")),
+            ("  hi \t", "TRIM R0", string("hi")),
+            ("héllo🦀", "REVERSE R0", string("🦀olléh")),
+        ];
+        for (text, instruction, expected) in cases {
+            assert_eq!(run(text, instruction).unwrap(), expected, "{instruction}");
+        }
+        assert!(run("abc", "CHARAT R0, 3").is_err());
+        assert!(run("abc", "SUBSTR R0, 4, 1").is_err());
+        assert!(run("abc", "LEN 5").is_err());
+        let Err(InterpreterError::TypeMismatch(operands)) = run("abc", "SUBSTR R0, R1, 1") else {
+            panic!("SUBSTR should need a number to start at");
+        };
+        assert_eq!(*operands, (string("abc"), string(",")));
+        let Err(InterpreterError::TypeMismatch(operands)) = run("abc", "UPPER 5") else {
+            panic!("UPPER should need a string");
+        };
+        assert_eq!(operands.0, Value::Number(5));
+
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .set_register("r0", Value::String("1,22,,é".to_string()))
+            .unwrap();
+        interpreter
+            .set_register("r1", Value::String(",".to_string()))
+            .unwrap();
+        interpreter.parse("SPLIT R0, R1, %10\nHALT\n").unwrap();
+        interpreter.run();
+        assert_eq!(interpreter.get_register("a").unwrap(), Value::Number(4));
+        for (address, expected) in [(10, "1"), (11, "22"), (12, ""), (13, "é")] {
+            assert_eq!(
+                interpreter.get_address(format!("%{address}")).unwrap(),
+                string(expected)
+            );
+        }
+    }
+    #[test]
+    fn test_conversion_instructions() {
+        let run = |r0: Value, instruction: &str| {
+            run_program_with(&[("r0", r0)], &format!("{instruction}\nHALT\n"))
+                .map(|interpreter| interpreter.get_register("a").unwrap())
+        };
+        let string = |s: &str| Value::String(s.to_string());
+        let cases = [
+            (Value::Number(-255), "ITOA R0", string("-255")),
+            (Value::Number(255), "ITOA R0, 16", string("ff")),
+            (Value::Number(5), "ITOA R0, 0b10", string("101")),
+            (Value::Number(0), "ITOA R0, 36", string("0")),
+            (
+                Value::Number(i64::MIN),
+                "ITOA R0, 2",
+                string(&format!("-1{}", "0".repeat(63))),
+            ),
+            (string(" 42 "), "ATOI R0", Value::Number(42)),
+            (string("-0x1F"), "ATOI R0", Value::Number(-31)),
+            (string("0b101"), "ATOI R0", Value::Number(5)),
+            (string("0xff"), "ATOI R0, 16", Value::Number(255)),
+            (string("FF"), "ATOI R0, 16", Value::Number(255)),
+            (string("z"), "ATOI R0, 36", Value::Number(35)),
+            (
+                string(&i64::MIN.to_string()),
+                "ATOI R0",
+                Value::Number(i64::MIN),
+            ),
+            (string("A"), "ORD R0", Value::Number(65)),
+            (string("🦀"), "ORD R0", Value::Number(0x1F980)),
+            (Value::Number(0xE9), "CHR R0", string("é")),
+            (Value::Number(7), "TYPE R0", Value::Number(TYPE_NUMBER)),
+            (string("7"), "TYPE R0", Value::Number(TYPE_STRING)),
+        ];
+        for (r0, instruction, expected) in cases {
+            assert_eq!(run(r0, instruction).unwrap(), expected, "{instruction}");
+        }
+        let failures = [
+            (Value::Number(5), "ITOA R0, 1"),
+            (Value::Number(5), "ITOA R0, 37"),
+            (string("5"), "ITOA R0"),
+            (string("12a"), "ATOI R0"),
+            (string("--5"), "ATOI R0"),
+            (string("0x10"), "ATOI R0, 10"),
+            (string("9223372036854775808"), "ATOI R0"),
+            (string("ab"), "ORD R0"),
+            (string(""), "ORD R0"),
+            (Value::Number(0xD800), "CHR R0"),
+            (Value::Number(-1), "CHR R0"),
+        ];
+        for (r0, instruction) in failures {
+            assert!(run(r0, instruction).is_err(), "{instruction}");
+        }
+    }
+    #[test]
+    fn test_string_and_character_literals() {
+        let cases = [
+            (r#"SET R0, "abc""#, "abc"),
+            (r#"SET R0, """#, ""),
+            (r#"SET R0, "tab\there\n""#, "tab\there\n"),
+            (r#"SET R0, "say \"hi\" \\ 'bye'""#, "say \"hi\" \\ 'bye'"),
+            (r#"SET R0, "caf\u{e9} \u{1F980}""#, "café 🦀"),
+            (r#"SET R0, "héllo; not a comment""#, "héllo; not a comment"),
+            ("SET R0, 'h'", "h"),
+            ("SET R0, ' '", " "),
+            (r"SET R0, '\''", "'"),
+            (r"SET R0, '\n'", "\n"),
+            (r"SET R0, '\u{E9}'", "é"),
+            ("SET R0, '🦀'", "🦀"),
+        ];
+        for (source, expected) in cases {
+            let interpreter = run_program(&format!("{source}\nHALT\n")).unwrap();
+            assert_eq!(
+                interpreter.get_register("r0").unwrap(),
+                Value::String(expected.to_string()),
+                "{source}"
+            );
+        }
+
+        let interpreter = run_program(
+            "DEFINE .name \"my_name\"\nJMP FINE .name=\"my_name\"\nHALT\nFINE:\nSET R0, 1\nHALT\n",
+        )
+        .unwrap();
+        assert_eq!(interpreter.get_register("r0").unwrap(), Value::Number(1));
+
+        for source in [
+            "SET R0, ''",
+            "SET R0, 'ab'",
+            r#"SET R0, "\q""#,
+            r#"SET R0, "\u{D800}""#,
+            r#"SET R0, "\u{}""#,
+            r#"SET R0, "\u{110000}""#,
+        ] {
+            assert!(
+                matches!(
+                    run_program(&format!("{source}\n")),
+                    Err(InterpreterError::ParseError(_))
+                ),
+                "{source}"
+            );
+        }
+
+        let instruction = Instruction::Print {
+            src: Operand::String("a \"b\"\n".to_string()),
+            newline: false,
+        };
+        assert_eq!(instruction.to_string(), r#"PRINT "a \"b\"\n""#);
+        let statements =
+            crate::ast_builder::parse_program(&format!("{instruction}\n"), None, None).unwrap();
+        let Statement::Instruction(parsed) = &statements[0].0 else {
+            panic!("PRINT should parse as an instruction");
+        };
+        assert_eq!(*parsed, instruction);
+    }
+    #[test]
+    fn test_constant_expressions() {
+        let interpreter = run_program(
+            "DEFINE .width 16\n\
+             DEFINE .height .width / 2\n\
+             DEFINE .size .width * .height\n\
+             DEFINE .base 10\n\
+             DEFINE .last .base + 15\n\
+             DEFINE .mixed -(.base - 2 * (3 + 1)) % 7\n\
+             DEFINE .greeting \"hello \" + \"world\"\n\
+             SET R0, .size\n\
+             SET R1, .last\n\
+             SET R2, .mixed\n\
+             SET R3, .greeting\n\
+             SET %(.base + 3), 7\n\
+             ADD (.size - 1), %(.base + 3)\n\
+             HALT\n",
+        )
+        .unwrap();
+        assert_eq!(interpreter.get_register("r0").unwrap(), Value::Number(128));
+        assert_eq!(interpreter.get_register("r1").unwrap(), Value::Number(25));
+        assert_eq!(interpreter.get_register("r2").unwrap(), Value::Number(5));
+        assert_eq!(
+            interpreter.get_register("r3").unwrap(),
+            Value::String("hello world".to_string())
+        );
+        assert_eq!(get_mem(&interpreter, 13), Some(Value::Number(7)));
+        assert_eq!(interpreter.get_register("a").unwrap(), Value::Number(134));
+
+        let errors = [
+            (
+                "DEFINE .a .missing + 1\n",
+                "The constant .missing is not defined",
+            ),
+            ("DEFINE .a 1 + \"abc\"\n", "Can't work out 1 + \"abc\""),
+            ("DEFINE .a 1 / 0\n", "Can't work out 1 / 0"),
+            ("SET R0, (.nope * 2)\n", "The constant .nope is not defined"),
+            (
+                "DEFINE .s \"x\"\nSET %(.s), 1\n",
+                "not a non-negative number",
+            ),
+            ("SET %(0 - 1), 1\n", "not a non-negative number"),
+        ];
+        for (source, expected) in errors {
+            let errors = compile_errors(source);
+            assert!(errors[0].1.contains(expected), "{source}: {}", errors[0].1);
+        }
+        let errors = compile_errors("NOP:\nDEFINE .a 1\nSET R0, (.a + .b)\n");
+        assert_eq!(errors[0].0, 3);
+
+        let statements =
+            ast_builder::parse_program("SET %(.base + 3), (-.a * (2 - 1))\n", None, None).unwrap();
+        let Statement::Instruction(instruction) = &statements[0].0 else {
+            panic!("SET should parse as an instruction");
+        };
+        assert_eq!(instruction.to_string(), "SET %(.base + 3), (-.a * (2 - 1))");
+        let reparsed = ast_builder::parse_program(&format!("{instruction}\n"), None, None).unwrap();
+        let Statement::Instruction(parsed) = &reparsed[0].0 else {
+            panic!("the printed SET should parse as an instruction");
+        };
+        assert_eq!(parsed, instruction);
+    }
+    #[test]
+    fn test_constant_diagnostics() {
+        assert_compile_errors(
+            "DEFINE .a 1\n\
+             SET R0, .b\n\
+             DEFINE .b 2\n\
+             DEFINE .a 3\n\
+             JMP END .missing=1\n\
+             DEFINE .c .d + 1\n\
+             DEFINE .d 4\n\
+             SET R1, .c\n\
+             END:\n\
+             HALT\n",
+            &[
+                (
+                    2,
+                    "The constant .b is used before it is defined on line 3, column 1",
+                ),
+                (4, "The constant .a is already defined on line 1, column 1"),
+                (5, "The constant .missing is not defined"),
+                (
+                    6,
+                    "Can't define .c: The constant .d is used before it is defined on line 7, \
+                     column 1",
+                ),
+                (8, "The constant .c couldn't be worked out"),
+            ],
+        );
+
+        let interpreter = run_program("DEFINE .a 1\nDEFINE .b .a + 1\nSET R0, .b\nHALT\n").unwrap();
+        assert_eq!(interpreter.get_register("r0").unwrap(), Value::Number(2));
+    }
+    #[test]
+    fn test_label_diagnostics() {
+        assert_compile_errors(
+            "loop:\n\
+             INC R0\n\
+             JMP lopo R0<10\n\
+             CALL print_total\n\
+             JMP LOOP\n\
+             CALL nowhere\n\
+             loop:\n\
+             HALT\n\
+             print_totals:\n\
+             RET\n",
+            &[
+                (3, "The label lopo is not defined. Did you mean loop?"),
+                (
+                    4,
+                    "The label print_total is not defined. Did you mean print_totals?",
+                ),
+                (5, "The label LOOP is not defined. Did you mean loop?"),
+                (6, "The label nowhere is not defined"),
+                (7, "The label loop is already defined on line 1, column 1"),
+            ],
+        );
+        assert_eq!(closest_match("ab", ["abc", "xy"]), Some("abc"));
+        assert_eq!(closest_match("abc", ["xyz"]), None);
+        assert_eq!(closest_match("end", ["edn", "and"]), Some("and"));
+    }
+    #[test]
+    fn test_local_and_anonymous_labels() {
+        let interpreter = run_program(
+            "MACRO count reg, limit\n\
+             @again:\n\
+             \x20   INC reg\n\
+             \x20   JMP @again reg<limit\n\
+             ENDM\n\
+             main:\n\
+             \x20   count R5, 2\n\
+             \x20   count R6, 3\n\
+             @loop:\n\
+             \x20   INC R0\n\
+             \x20   JMP @loop R0<3\n\
+             \x20   CALL other\n\
+             \x20   JMP +\n\
+             \x20   SET R2, 99\n\
+             +:\n\
+             \x20   JMP ++\n\
+             +:\n\
+             \x20   SET R3, 99\n\
+             +:\n\
+             \x20   HALT\n\
+             other:\n\
+             @loop:\n\
+             \x20   INC R1\n\
+             \x20   JMP @loop R1<5\n\
+             -:\n\
+             \x20   INC R4\n\
+             \x20   JMP - R4<2\n\
+             \x20   RET\n",
+        )
+        .unwrap();
+        let registers = ["r0", "r1", "r2", "r3", "r4", "r5", "r6"]
+            .map(|register| interpreter.get_register(register).unwrap());
+        assert_eq!(registers, [3, 5, 0, 0, 2, 2, 3].map(Value::Number));
+
+        assert_compile_errors(
+            "JMP +\n\
+             JMP --\n\
+             -:\n\
+             first:\n\
+             @x:\n\
+             @x:\n\
+             JMP @missing\n\
+             JMP @y\n\
+             second:\n\
+             @x:\n\
+             HALT\n",
+            &[
+                (1, "There is no +: label after this"),
+                (2, "There aren't 2 -: labels before this"),
+                (
+                    6,
+                    "The label @x in first is already defined on line 5, column 1",
+                ),
+                (7, "The label @missing in first is not defined"),
+                (
+                    8,
+                    "The label @y in first is not defined. Did you mean @x in first?",
+                ),
+            ],
+        );
+    }
+    #[test]
+    fn test_anonymous_labels_in_macros_and_includes() {
+        let interpreter = run_program(
+            "MACRO skip reg\n\
+             \x20   JMP +\n\
+             \x20   SET reg, 99\n\
+             +:\n\
+             \x20   SET R3, 1\n\
+             ENDM\n\
+             \x20   JMP +\n\
+             \x20   skip R1\n\
+             \x20   SET R2, 1\n\
+             +:\n\
+             \x20   skip R4\n\
+             \x20   HALT\n",
+        )
+        .unwrap();
+        let registers =
+            ["r1", "r2", "r3", "r4"].map(|register| interpreter.get_register(register).unwrap());
+        assert_eq!(registers, [0, 0, 1, 0].map(Value::Number));
+
+        let loader = MemoryLoader::new()
+            .file(
+                "main.asm",
+                "JMP +\n\
+                 INCLUDE \"lib.asm\"\n\
+                 SET R2, 1\n\
+                 +:\n\
+                 HALT\n",
+            )
+            .file("lib.asm", "+:\nSET R1, 1\n")
+            .file("back.asm", "-:\nINCLUDE \"jump.asm\"\n")
+            .file("jump.asm", "JMP -\n");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_source_loader(loader);
+        interpreter.parse_file("main.asm").unwrap();
+        while interpreter.running.load(Ordering::SeqCst) {
+            interpreter.step().unwrap();
+        }
+        let registers = ["r1", "r2"].map(|register| interpreter.get_register(register).unwrap());
+        assert_eq!(registers, [0, 0].map(Value::Number));
+
+        let Err(InterpreterError::Compile(diagnostics)) = interpreter.parse_file("back.asm") else {
+            panic!("jump.asm has no -: label to jump back to");
+        };
+        let messages: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec!["jump.asm, line 1, column 1: There is no -: label before this"]
+        );
+    }
+    #[test]
+    fn test_macros() {
+        let interpreter = run_program(
+            "MACRO add_to dest, amount\n\
+             \x20   DEFINE .step amount\n\
+             \x20   ADD dest, .step\n\
+             \x20   MOV A, dest\n\
+             ENDM\n\
+             macro count_to reg, limit ; counts up\n\
+             again:\n\
+             \x20   add_to reg, 1\n\
+             \x20   JMP again reg<limit\n\
+             endm\n\
+             DEFINE .step 100\n\
+             count_to R0, 5\n\
+             count_to R1, .step\n\
+             add_to R2, .step\n\
+             HALT\n",
+        )
+        .unwrap();
+        assert_eq!(interpreter.get_register("r0").unwrap(), Value::Number(5));
+        assert_eq!(interpreter.get_register("r1").unwrap(), Value::Number(100));
+        assert_eq!(interpreter.get_register("r2").unwrap(), Value::Number(100));
+
+        let Err(InterpreterError::Compile(diagnostics)) = run_program(
+            "MACRO jump_to target\n\
+             \x20   SET R0, .missing\n\
+             \x20   JMP target\n\
+             ENDM\n\
+             jump_to nowhere\n",
+        ) else {
+            panic!("the program should fail to compile");
+        };
+        let messages: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Line 2, column 5 in macro jump_to, called on line 5, column 1: \
+                 The constant .missing is not defined",
+                "Line 3, column 5 in macro jump_to, called on line 5, column 1: \
+                 The label nowhere is not defined",
+            ]
+        );
+
+        let errors = [
+            (
+                "MACRO twice x\nINC x\nENDM\ntwic R0\n",
+                "There is no instruction or macro called twic. Did you mean twice?",
+            ),
+            (
+                "MACRO twice x\nINC x\nENDM\ntwice R0, R1\n",
+                "The macro twice takes 1 arguments, but was given 2",
+            ),
+            (
+                "MACRO twice x\nINC x\nENDM\nMACRO twice x\nENDM\n",
+                "The macro twice is already defined on line 1, column 1",
+            ),
+            (
+                "MACRO bad x\nMOV x, 5\nENDM\nbad 1\n",
+                "In macro bad on line 2",
+            ),
+            (
+                "MACRO forever\nforever\nENDM\nforever\n",
+                "more than 64 deep",
+            ),
+            (
+                "MACRO add a, b\nENDM\n",
+                "The macro add has the same name as an instruction",
+            ),
+            (
+                "MACRO in x\nENDM\n",
+                "The macro in has the same name as an instruction",
+            ),
+            ("SET R0 5\n", "expected EOI"),
+            ("ADD R0\n", "expected EOI"),
+            ("JMP\n", "1:4\n"),
+            ("SUBSTR R0, 1\n", "1:12\n"),
+        ];
+        for (source, expected) in errors {
+            let Err(InterpreterError::ParseError(error)) = run_program(source) else {
+                panic!("{source} should fail to parse");
+            };
+            assert!(error.to_string().contains(expected), "{error}");
+        }
+    }
+    #[test]
+    fn test_macros_named_after_instructions() {
+        // Every keyword that starts one of the grammar's statements, so a new instruction can't
+        // be missed from the keywords that macros can't be named after
+        let grammar = include_str!("../asm.pest");
+        let rule = |name: &str| {
+            grammar
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.trim_start().strip_prefix('='))
+                .unwrap_or_else(|| panic!("the grammar should have a {name} rule"))
+        };
+        let keywords: Vec<&str> = rule("STATEMENT")
+            .trim_matches(|c| matches!(c, ' ' | '_' | '{' | '}'))
+            .split('|')
+            .map(str::trim)
+            .filter(|name| *name != "MACROCALL")
+            .flat_map(|name| {
+                let keywords = rule(name).split('~').next().unwrap_or_default();
+                keywords.split('"').skip(1).step_by(2)
+            })
+            .collect();
+        assert!(keywords.contains(&"divmod") && keywords.contains(&"STRING"));
+        for keyword in keywords {
+            let Err(InterpreterError::ParseError(error)) =
+                run_program(&format!("MACRO {keyword} x\nENDM\n"))
+            else {
+                panic!("a macro called {keyword} should fail to parse");
+            };
+            assert!(
+                error.to_string().contains(&format!(
+                    "The macro {keyword} has the same name as an instruction"
+                )),
+                "{error}"
+            );
+        }
+    }
+    #[test]
+    fn test_includes() {
+        let loader = MemoryLoader::new()
+            .file(
+                "main.asm",
+                "INCLUDE \"lib/math.asm\"\n\
+                 include \"lib/util.asm\" ; already included by math.asm\n\
+                 SET R0, 21\n\
+                 double R0\n\
+                 HALT\n",
+            )
+            .file(
+                "lib/math.asm",
+                "INCLUDE \"util.asm\"\n\
+                 DEFINE .two 2\n\
+                 MACRO double reg\n\
+                 \x20   MUL reg, .two\n\
+                 \x20   MOV A, reg\n\
+                 ENDM\n",
+            )
+            .file("lib/util.asm", "INC R1\n")
+            .file("a.asm", "INCLUDE \"b.asm\"\n")
+            .file("b.asm", "INCLUDE \"./a.asm\"\n")
+            .file("self.asm", "INCLUDE \"self.asm\"\n")
+            .file(
+                "lib/bad.asm",
+                "MACRO jump_to target\n\
+                 \x20   JMP target\n\
+                 ENDM\n\
+                 SET R0, .missing\n",
+            )
+            .file("bad.asm", "INCLUDE \"lib/bad.asm\"\njump_to nowhere\n");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_source_loader(loader.clone());
+        interpreter.parse_file("main.asm").unwrap();
+        while interpreter.running.load(Ordering::SeqCst) {
+            interpreter.step().unwrap();
+        }
+        assert_eq!(interpreter.get_register("r0").unwrap(), Value::Number(42));
+        assert_eq!(interpreter.get_register("r1").unwrap(), Value::Number(1));
+        interpreter.parse_file("./main.asm").unwrap();
+
+        let Err(InterpreterError::Compile(diagnostics)) = interpreter.parse_file("bad.asm") else {
+            panic!("bad.asm should fail to compile");
+        };
+        let messages: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "lib/bad.asm, line 2, column 5 in macro jump_to, called on bad.asm, line 2, \
+                 column 1: The label nowhere is not defined",
+                "lib/bad.asm, line 4, column 1: The constant .missing is not defined",
+            ]
+        );
+
+        let errors = [
+            (
+                "a.asm",
+                "In b.asm on line 1, column 1: a.asm can't include itself: a.asm -> b.asm -> a.asm",
+            ),
+            (
+                "./self.asm",
+                "self.asm can't include itself: self.asm -> self.asm",
+            ),
+            (
+                "missing.asm",
+                "Failed to load missing.asm: There is no file called missing.asm",
+            ),
+        ];
+        for (file, expected) in errors {
+            let error = interpreter.parse_file(file).unwrap_err();
+            assert!(error.to_string().contains(expected), "{error}");
+        }
+        let error = interpreter.parse("INCLUDE \"lib/nope.asm\"\n").unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Can't include lib/nope.asm: There is no file called lib/nope.asm"),
+            "{error}"
+        );
+        let error = Interpreter::new()
+            .parse("INCLUDE \"lib/util.asm\"\n")
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("there is nowhere to load files from"),
+            "{error}"
+        );
+    }
+    #[test]
+    fn test_data_directives() {
+        let interpreter = run_program(
+            "DEFINE .base 16\n\
+             DATA %(.base), 1, 0x2, -3, \"text\", 'c', (.base * 2)\n\
+             FILL %32, 10, 7\n\
+             string %64, \"hi\\n\"\n\
+             FILL 0x00ff00\n\
+             HALT\n",
+        )
+        .unwrap();
+        let data: Vec<Value> = (16..22)
+            .filter_map(|addr| get_mem(&interpreter, addr))
+            .collect();
+        assert_eq!(
+            data,
+            vec![
+                Value::Number(1),
+                Value::Number(2),
+                Value::Number(-3),
+                Value::String("text".to_string()),
+                Value::String("c".to_string()),
+                Value::Number(32),
+            ]
+        );
+        for addr in 32..42 {
+            assert_eq!(get_mem(&interpreter, addr), Some(Value::Number(7)));
+        }
+        assert_eq!(get_mem(&interpreter, 42), Some(Value::Number(0)));
+        let text: Vec<Value> = (64..68)
+            .filter_map(|addr| get_mem(&interpreter, addr))
+            .collect();
+        assert_eq!(
+            text,
+            ["h", "i", "\n"]
+                .map(|c| Value::String(c.to_string()))
+                .into_iter()
+                .chain([Value::Number(0)])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(interpreter.memory_slots_used().unwrap(), 19);
+        assert_eq!(get_pixel(&interpreter, 0, 0), (0, 255, 0));
+
+        let errors = [
+            (
+                "FILL %0, -1, 0\n",
+                "FILL needs a count that's a positive number, not -1",
+            ),
+            (
+                "FILL %0, 0, 7\n",
+                "FILL needs a count that's a positive number, not 0",
+            ),
+            ("STRING %0, 5\n", "STRING needs a string, not the number 5"),
+            (
+                "DATA %(.missing), 1\n",
+                "The constant .missing is not defined",
+            ),
+            ("DATA %(1 - 2), 1\n", "not a non-negative number"),
+            ("FILL %300, 1000, 0\n", "Can't put data at %512"),
+        ];
+        for (source, expected) in errors {
+            let errors = compile_errors(source);
+            assert_eq!(errors.len(), 1, "{errors:?}");
+            assert!(errors[0].1.contains(expected), "{errors:?}");
+        }
+    }
+    #[test]
+    fn test_variables() {
+        let interpreter = run_program(
+            "DEFINE .size 4\n\
+             VAR .counter\n\
+             VAR .buffer[.size * 2]\n\
+             var .last\n\
+             DATA %.buffer, 10, 20\n\
+             MACRO bump\n\
+             \x20   VAR .tmp\n\
+             \x20   SET %.tmp, 1\n\
+             \x20   INC %.counter\n\
+             ENDM\n\
+             bump\n\
+             bump\n\
+             SET %.buffer+2, 30\n\
+             MOV %.buffer+1, R0\n\
+             ADD %.buffer, %.buffer+1\n\
+             STORE A, %.last\n\
+             SET R1, .last\n\
+             SET %.buffer+.size, 5\n\
+             HALT\n",
+        )
+        .unwrap();
+        let memory: Vec<(usize, Value)> = (0..12)
+            .map(|addr| (addr, get_mem(&interpreter, addr).unwrap()))
+            .filter(|(_, value)| *value != Value::Number(0))
+            .collect();
+        assert_eq!(
+            memory,
+            [
+                (0, 2),
+                (1, 10),
+                (2, 20),
+                (3, 30),
+                (5, 5),
+                (9, 30),
+                (10, 1),
+                (11, 1)
+            ]
+            .map(|(addr, value)| (addr, Value::Number(value)))
+        );
+        assert_eq!(interpreter.get_register("r0").unwrap(), Value::Number(20));
+        assert_eq!(interpreter.get_register("r1").unwrap(), Value::Number(9));
+
+        let errors = [
+            (
+                "VAR .big[200]\nVAR .more[57]\n",
+                "There isn't enough memory for .more, which needs 57 slots when only 56 are left",
+            ),
+            (
+                "VAR .x[0]\n",
+                "VAR .x needs a size that's a positive number, not 0",
+            ),
+            (
+                "VAR .x\nVAR .x\n",
+                "The constant .x is already defined on line 1, column 1",
+            ),
+            (
+                "DEFINE .x 1\nvar .x\n",
+                "The constant .x is already defined on line 1, column 1",
+            ),
+            ("SET %.nothing, 1\n", "The constant .nothing is not defined"),
+        ];
+        for (source, expected) in errors {
+            let errors = compile_errors(source);
+            assert_eq!(errors.len(), 1, "{errors:?}");
+            assert_eq!(errors[0].1, expected);
+        }
+    }
 }
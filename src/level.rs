@@ -0,0 +1,946 @@
+/*BSD 3-Clause License
+
+Copyright (c) 2025, Jeffrey Smith
+
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+
+1. Redistributions of source code must retain the above copyright notice, this
+   list of conditions and the following disclaimer.
+
+2. Redistributions in binary form must reproduce the above copyright notice,
+   this list of conditions and the following disclaimer in the documentation
+   and/or other materials provided with the distribution.
+
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+
+THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Levels describe a puzzle: what the machine looks like when the program starts, what it is fed
+//! through the input ports, and what it has to produce. A level is written in TOML:
+//!
+//! ```toml
+//! name = "Adder"
+//! description = "Read a number from each of IN0 and IN1, and write their sum to OUT0"
+//! allowed_instructions = ["IN", "OUT", "ADD", "JMP"]
+//! allowed_registers = ["R0", "R1"]
+//!
+//! [limits]
+//! max_cycles = 1000
+//! max_instructions = 10
+//!
+//! [[tests]]
+//! name = "Small numbers"
+//! inputs = { 0 = [1, 2], 1 = [3, 4] }
+//! registers = { r7 = 1 }
+//! seed = 42
+//! memory = { 16 = "start" }
+//! expected = { outputs = { 0 = [4, 6] } }
+//! ```
+//!
+//! A test case ends when the program halts or waits on an input port that has run dry. Each
+//! test starts from a fresh machine. Everything under `expected` is optional, and only what is
+//! listed there is checked: `outputs` per port, `memory` per address, `registers` by name and
+//! `console` for everything written with `PRINT`. `console_input` is what `READ` will see,
+//! and `seed` seeds `RAND`.
+//!
+//! Levels can also generate test cases, so that solutions can't be fitted to the fixed ones. The
+//! inputs are made by generators, and the expected outputs and console text come from running a
+//! reference solution on the same inputs:
+//!
+//! ```toml
+//! [random]
+//! count = 20
+//! reference = """
+//! LOOP:
+//!     IN 0, R0
+//!     IN 1, R1
+//!     ADD R0, R1
+//!     OUT A, 0
+//!     JMP LOOP
+//! """
+//! inputs = [
+//!     { type = "number", port = 0, min = -100, max = 100, count = 5 },
+//!     { type = "number", port = 1, min = -100, max = 100, count = 5 },
+//! ]
+//! ```
+//!
+//! Generators are `number` (`count` numbers from `min` to `max`), `string` (`count` strings of
+//! `min_length` to `max_length` characters from `alphabet`) and `list` (`min_length` to
+//! `max_length` numbers from `min` to `max`, `sorted` if asked, followed by an optional
+//! `terminator`). Every generated test case has its own seed, which is shown when it fails so it
+//! can be run again with `Level::verify_with_seeds`.
+
+use crate::console::SharedBuffer;
+use crate::error::{InterpreterError, LevelError};
+use crate::interpreter::PORTS;
+use crate::restrictions::Restrictions;
+use crate::rng::Rng;
+use crate::score::Score;
+use crate::source::SourceLoader;
+use crate::{Interpreter, Value};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+const DEFAULT_MAX_CYCLES: usize = 100_000;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Level {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// The instructions a solution may use. Leaving this empty allows every instruction
+    #[serde(default)]
+    pub allowed_instructions: Vec<String>,
+    /// The registers a solution may use, on top of `A`, `F` and `SP`. Leaving this empty allows
+    /// every register
+    #[serde(default)]
+    pub allowed_registers: Vec<String>,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub tests: Vec<TestCase>,
+    /// Test cases to generate on top of `tests`
+    pub random: Option<RandomTests>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// How many instructions a single test case may execute before it is failed
+    pub max_cycles: usize,
+    /// How many instructions the program may contain
+    pub max_instructions: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_cycles: DEFAULT_MAX_CYCLES,
+            max_instructions: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    /// Registers to set before the program starts
+    pub registers: BTreeMap<String, Value>,
+    /// Memory to set before the program starts
+    pub memory: BTreeMap<usize, Value>,
+    /// Values queued up on each input port
+    pub inputs: BTreeMap<usize, Vec<Value>>,
+    /// Text for `READ` and `READLINE`
+    pub console_input: String,
+    /// The seed for `RAND`
+    pub seed: u64,
+    pub expected: Expected,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Expected {
+    /// Everything that should have been written to each output port, in order
+    pub outputs: BTreeMap<usize, Vec<Value>>,
+    pub memory: BTreeMap<usize, Value>,
+    pub registers: BTreeMap<String, Value>,
+    /// Everything that should have been written with `PRINT` and `PRINTLN`
+    pub console: Option<String>,
+}
+
+const fn default_random_count() -> usize {
+    10
+}
+const fn default_generator_count() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RandomTests {
+    /// How many test cases to generate
+    #[serde(default = "default_random_count")]
+    pub count: usize,
+    /// A program that solves the level. What it outputs is what solutions are expected to output
+    pub reference: String,
+    /// What to queue up on the input ports
+    pub inputs: Vec<Generator>,
+}
+
+/// Makes random values for an input port
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Generator {
+    Number {
+        port: usize,
+        min: i64,
+        max: i64,
+        #[serde(default = "default_generator_count")]
+        count: usize,
+    },
+    String {
+        port: usize,
+        alphabet: String,
+        min_length: usize,
+        max_length: usize,
+        #[serde(default = "default_generator_count")]
+        count: usize,
+    },
+    List {
+        port: usize,
+        min: i64,
+        max: i64,
+        min_length: usize,
+        max_length: usize,
+        #[serde(default)]
+        sorted: bool,
+        /// Written after the list, so a program knows where it ends
+        terminator: Option<Value>,
+    },
+}
+
+impl Generator {
+    fn check(&self) -> Result<(), String> {
+        match self {
+            Generator::Number { min, max, .. } if min > max => {
+                Err(format!("number generator range {min}..={max} is empty"))
+            }
+            Generator::String { alphabet, .. } if alphabet.is_empty() => {
+                Err("string generator has an empty alphabet".to_string())
+            }
+            Generator::List { min, max, .. } if min > max => {
+                Err(format!("list generator range {min}..={max} is empty"))
+            }
+            Generator::String {
+                min_length,
+                max_length,
+                ..
+            }
+            | Generator::List {
+                min_length,
+                max_length,
+                ..
+            } if min_length > max_length => Err(format!(
+                "generator lengths {min_length}..={max_length} are empty"
+            )),
+            _ => Ok(()),
+        }
+    }
+    /// Make the values for one test case
+    fn generate(&self, rng: &mut Rng) -> (usize, Vec<Value>) {
+        match self {
+            Generator::Number {
+                port,
+                min,
+                max,
+                count,
+            } => (
+                *port,
+                (0..*count)
+                    .map(|_| Value::Number(rng.range(*min, *max)))
+                    .collect(),
+            ),
+            Generator::String {
+                port,
+                alphabet,
+                min_length,
+                max_length,
+                count,
+            } => {
+                let alphabet: Vec<char> = alphabet.chars().collect();
+                let values = (0..*count)
+                    .map(|_| {
+                        let length = random_length(rng, *min_length, *max_length);
+                        let string = (0..length)
+                            .map(|_| alphabet[rng.index(alphabet.len())])
+                            .collect();
+                        Value::String(string)
+                    })
+                    .collect();
+                (*port, values)
+            }
+            Generator::List {
+                port,
+                min,
+                max,
+                min_length,
+                max_length,
+                sorted,
+                terminator,
+            } => {
+                let length = random_length(rng, *min_length, *max_length);
+                let mut numbers: Vec<i64> = (0..length).map(|_| rng.range(*min, *max)).collect();
+                if *sorted {
+                    numbers.sort_unstable();
+                }
+                let mut values: Vec<Value> = numbers.into_iter().map(Value::Number).collect();
+                values.extend(terminator.clone());
+                (*port, values)
+            }
+        }
+    }
+}
+
+fn random_length(rng: &mut Rng, min: usize, max: usize) -> usize {
+    min + rng.index(max - min + 1)
+}
+
+impl Level {
+    /// Read a level from TOML
+    /// # Errors
+    /// Returns an error if the TOML isn't a valid level
+    pub fn from_toml(contents: &str) -> Result<Level, LevelError> {
+        let level: Level = toml::from_str(contents)?;
+        if level.tests.is_empty() && level.random.is_none() {
+            return Err(LevelError::Invalid(
+                "a level needs fixed or random tests".to_string(),
+            ));
+        }
+        if let Some(random) = &level.random {
+            for generator in &random.inputs {
+                generator.check().map_err(LevelError::Invalid)?;
+            }
+        }
+        Ok(level)
+    }
+    /// Read a level from a TOML file
+    /// # Errors
+    /// Returns an error if the file can't be read or isn't a valid level
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Level, LevelError> {
+        Level::from_toml(&std::fs::read_to_string(path)?)
+    }
+    /// The instructions and registers solutions to this level may use
+    #[must_use]
+    pub fn restrictions(&self) -> Restrictions {
+        let mut restrictions = Restrictions::new();
+        if !self.allowed_instructions.is_empty() {
+            restrictions = restrictions.allow_instructions(&self.allowed_instructions);
+        }
+        if !self.allowed_registers.is_empty() {
+            restrictions = restrictions.allow_registers(&self.allowed_registers);
+        }
+        restrictions
+    }
+    /// Run a program against every test case in the level, generating new random test cases
+    /// every time
+    /// # Errors
+    /// Returns an error if the program doesn't parse, if the level sets up a register or
+    /// address that doesn't exist, or if the reference solution fails. Programs that fail a test
+    /// case or break the restrictions of the level are reported in the `VerificationReport`, not
+    /// as an error
+    pub fn verify<T: AsRef<str>>(
+        &self,
+        program: T,
+    ) -> Result<VerificationReport, InterpreterError> {
+        let count = self.random.as_ref().map_or(0, |random| random.count);
+        let mut rng = Rng::new(Rng::random_seed());
+        let seeds: Vec<u64> = (0..count).map(|_| rng.next_u64()).collect();
+        self.verify_with_seeds(program, &seeds)
+    }
+    /// Run a program against the fixed test cases in the level, and one random test case for
+    /// each seed. Use this to replay random test cases that failed
+    /// # Errors
+    /// The same as `verify`
+    pub fn verify_with_seeds<T: AsRef<str>>(
+        &self,
+        program: T,
+        seeds: &[u64],
+    ) -> Result<VerificationReport, InterpreterError> {
+        self.verify_solution(&Solution::Source(program.as_ref()), seeds)
+    }
+    /// Run the program in the file `name` against every test case in the level, like `verify`.
+    /// The file and everything it includes are loaded with `loader`
+    /// # Errors
+    /// The same as `verify`, or if the file can't be loaded
+    pub fn verify_file(
+        &self,
+        loader: Arc<dyn SourceLoader>,
+        name: &str,
+    ) -> Result<VerificationReport, InterpreterError> {
+        let count = self.random.as_ref().map_or(0, |random| random.count);
+        let mut rng = Rng::new(Rng::random_seed());
+        let seeds: Vec<u64> = (0..count).map(|_| rng.next_u64()).collect();
+        self.verify_file_with_seeds(loader, name, &seeds)
+    }
+    /// Run the program in the file `name` like `verify_with_seeds`, loading it and everything it
+    /// includes with `loader`
+    /// # Errors
+    /// The same as `verify_file`
+    pub fn verify_file_with_seeds(
+        &self,
+        loader: Arc<dyn SourceLoader>,
+        name: &str,
+        seeds: &[u64],
+    ) -> Result<VerificationReport, InterpreterError> {
+        self.verify_solution(&Solution::File(loader, name), seeds)
+    }
+    fn verify_solution(
+        &self,
+        program: &Solution,
+        seeds: &[u64],
+    ) -> Result<VerificationReport, InterpreterError> {
+        let mut interpreter = Interpreter::new();
+        interpreter.restrict(self.restrictions());
+        let problems = match program.parse_into(&mut interpreter) {
+            Ok(()) => self.check_program(&interpreter),
+            Err(InterpreterError::Compile(diagnostics)) => {
+                diagnostics.iter().map(ToString::to_string).collect()
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut report = VerificationReport {
+            level: self.name.clone(),
+            problems,
+            instructions: interpreter.instructions().count(),
+            tests: Vec::new(),
+        };
+        if report.problems.is_empty() {
+            for (i, test) in self.tests.iter().enumerate() {
+                report.tests.push(self.run_test(i, test, program)?);
+            }
+            for (i, seed) in seeds.iter().enumerate() {
+                if let Some(test) = self.random_test(*seed)? {
+                    let mut result = self.run_test(self.tests.len() + i, &test, program)?;
+                    result.seed = Some(*seed);
+                    report.tests.push(result);
+                }
+            }
+        }
+        Ok(report)
+    }
+    /// Generate the random test case for `seed`, or `None` if the level doesn't have random
+    /// tests
+    /// # Errors
+    /// Returns an error if the reference solution doesn't parse, or fails the test case
+    pub fn random_test(&self, seed: u64) -> Result<Option<TestCase>, InterpreterError> {
+        let Some(random) = &self.random else {
+            return Ok(None);
+        };
+        let mut rng = Rng::new(seed);
+        let mut test = TestCase {
+            name: format!("Random (seed {seed})"),
+            seed,
+            ..TestCase::default()
+        };
+        for generator in &random.inputs {
+            let (port, values) = generator.generate(&mut rng);
+            test.inputs.entry(port).or_default().extend(values);
+        }
+
+        let (interpreter, console, failures) =
+            self.run_program(&test, &Solution::Source(&random.reference))?;
+        if !failures.is_empty() {
+            return Err(InterpreterError::Other(format!(
+                "The reference solution failed the random test with seed {seed}: {}",
+                failures.join(", ")
+            )));
+        }
+        for port in 0..PORTS {
+            test.expected
+                .outputs
+                .insert(port, interpreter.take_output(port)?);
+        }
+        test.expected.console = Some(console.contents());
+        Ok(Some(test))
+    }
+
+    /// Check the rules of the level that don't need the program to run, other than its
+    /// restrictions, which `parse` already checks
+    fn check_program(&self, interpreter: &Interpreter) -> Vec<String> {
+        let mut problems = Vec::new();
+        let count = interpreter.instructions().count();
+        if let Some(max) = self.limits.max_instructions
+            && count > max
+        {
+            problems.push(format!(
+                "The program has {count} instructions, but only {max} are allowed"
+            ));
+        }
+        problems
+    }
+
+    fn run_test(
+        &self,
+        index: usize,
+        test: &TestCase,
+        program: &Solution,
+    ) -> Result<TestResult, InterpreterError> {
+        let (interpreter, console, mut failures) = self.run_program(test, program)?;
+
+        for (port, expected) in &test.expected.outputs {
+            let actual = interpreter.take_output(*port)?;
+            if &actual != expected {
+                failures.push(format!(
+                    "Output port {port}: expected [{}], got [{}]",
+                    join(expected),
+                    join(&actual)
+                ));
+            }
+        }
+        for (address, expected) in &test.expected.memory {
+            let actual = interpreter.read_memory(*address)?;
+            if &actual != expected {
+                failures.push(format!(
+                    "Memory %{address}: expected {expected}, got {actual}"
+                ));
+            }
+        }
+        for (register, expected) in &test.expected.registers {
+            let actual = interpreter.get_register(register)?;
+            if &actual != expected {
+                failures.push(format!(
+                    "Register {register}: expected {expected}, got {actual}"
+                ));
+            }
+        }
+        if let Some(expected) = &test.expected.console {
+            let actual = console.contents();
+            if &actual != expected {
+                failures.push(format!("Console: expected {expected:?}, got {actual:?}"));
+            }
+        }
+
+        Ok(TestResult {
+            name: if test.name.is_empty() {
+                format!("Test {}", index + 1)
+            } else {
+                test.name.clone()
+            },
+            cycles: interpreter.cycles.load(Ordering::SeqCst),
+            memory: interpreter.memory_slots_used()?,
+            stack: interpreter.peak_stack_depth.load(Ordering::SeqCst),
+            seed: None,
+            failures,
+        })
+    }
+
+    /// Run `program` on the setup of `test` until it finishes, returning the machine it ran on,
+    /// what it printed, and why it didn't finish if it didn't
+    fn run_program(
+        &self,
+        test: &TestCase,
+        program: &Solution,
+    ) -> Result<(Interpreter, SharedBuffer, Vec<String>), InterpreterError> {
+        let mut interpreter = Interpreter::new();
+        let console = SharedBuffer::new();
+        interpreter.set_output(console.clone());
+        interpreter.set_input(Cursor::new(test.console_input.clone()));
+        interpreter.seed(test.seed);
+        for (register, value) in &test.registers {
+            interpreter.set_register(register, value.clone())?;
+        }
+        for (port, values) in &test.inputs {
+            for value in values {
+                interpreter.push_input(*port, value.clone())?;
+            }
+        }
+        program.parse_into(&mut interpreter)?;
+        // The test's memory goes in after the program's data, so the data can't replace it
+        for (address, value) in &test.memory {
+            interpreter.write_memory(*address, value.clone())?;
+        }
+
+        let mut failures = Vec::new();
+        while interpreter.running.load(Ordering::SeqCst)
+            && !interpreter.waiting.load(Ordering::SeqCst)
+        {
+            if interpreter.cycles.load(Ordering::SeqCst) >= self.limits.max_cycles {
+                failures.push(format!(
+                    "Did not finish within {} cycles",
+                    self.limits.max_cycles
+                ));
+                break;
+            }
+            if let Err(e) = interpreter.step() {
+                failures.push(format!("Error: {e}"));
+                break;
+            }
+        }
+        Ok((interpreter, console, failures))
+    }
+}
+
+/// The program being checked against a level
+enum Solution<'a> {
+    /// Source that can't include anything
+    Source(&'a str),
+    /// A file, loaded along with the files it includes
+    File(Arc<dyn SourceLoader>, &'a str),
+}
+
+impl Solution<'_> {
+    fn parse_into(&self, interpreter: &mut Interpreter) -> Result<(), InterpreterError> {
+        match self {
+            Solution::Source(source) => interpreter.parse(source),
+            Solution::File(loader, name) => {
+                interpreter.set_source_loader(Arc::clone(loader));
+                interpreter.parse_file(name)
+            }
+        }
+    }
+}
+
+fn join(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The outcome of running a program against a level
+#[derive(Debug, Clone)]
+pub struct VerificationReport {
+    pub level: String,
+    /// Rules of the level the program breaks. Test cases aren't run when there are any
+    pub problems: Vec<String>,
+    /// How many instructions the program has
+    pub instructions: usize,
+    pub tests: Vec<TestResult>,
+}
+
+impl VerificationReport {
+    /// Whether the program solves the level
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+            && !self.tests.is_empty()
+            && self.tests.iter().all(TestResult::passed)
+    }
+    /// The score of the program, if it solves the level
+    #[must_use]
+    pub fn score(&self) -> Option<Score> {
+        self.passed()
+            .then(|| Score::new(self.instructions, &self.tests))
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Level: {}", self.level)?;
+        for problem in &self.problems {
+            writeln!(f, "  {problem}")?;
+        }
+        for test in &self.tests {
+            let status = if test.passed() { "PASS" } else { "FAIL" };
+            writeln!(f, "  [{status}] {} ({} cycles)", test.name, test.cycles)?;
+            for failure in &test.failures {
+                writeln!(f, "      {failure}")?;
+            }
+        }
+        match self.score() {
+            Some(score) => write!(f, "Solved: {score}"),
+            None => write!(f, "Not solved"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    /// How many instructions were executed
+    pub cycles: usize,
+    /// How many different RAM slots the program wrote to
+    pub memory: usize,
+    /// The deepest the stack got
+    pub stack: usize,
+    /// The seed the test case was generated from, if it was generated
+    pub seed: Option<u64>,
+    /// Why the test failed. Empty if it passed
+    pub failures: Vec<String>,
+}
+
+impl TestResult {
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemoryLoader;
+
+    const ADDER: &str = r#"
+name = "Adder"
+allowed_instructions = ["IN", "OUT", "ADD", "JMP"]
+allowed_registers = ["R0", "R1"]
+
+[limits]
+max_cycles = 100
+max_instructions = 5
+
+[[tests]]
+name = "Small numbers"
+inputs = { 0 = [1, 2], 1 = [3, 4] }
+expected = { outputs = { 0 = [4, 6] } }
+
+[[tests]]
+inputs = { 0 = [-10], 1 = [10] }
+registers = { r7 = 5 }
+expected = { outputs = { 0 = [0] }, registers = { r7 = 5 } }
+"#;
+
+    const SOLUTION: &str = "LOOP:\nIN 0, R0\nIN 1, R1\nADD R0, R1\nOUT A, 0\nJMP LOOP\n";
+
+    #[test]
+    fn test_verify_solution() {
+        let level = Level::from_toml(ADDER).unwrap();
+        let report = level.verify(SOLUTION).unwrap();
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.tests.len(), 2);
+        assert_eq!(report.tests[1].name, "Test 2");
+        assert_eq!(report.tests[0].cycles, 10);
+        assert_eq!(
+            report.score(),
+            Some(Score {
+                cycles: 15,
+                instructions: 5,
+                memory: 0,
+                stack: 0
+            })
+        );
+    }
+    #[test]
+    fn test_verify_file() {
+        let level = Level::from_toml(ADDER).unwrap();
+        let loader = MemoryLoader::new()
+            .file("main.asm", "INCLUDE \"lib/adder.asm\"\n")
+            .file("lib/adder.asm", SOLUTION);
+        let report = level.verify_file(Arc::new(loader), "main.asm").unwrap();
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.instructions, 5);
+
+        let error = level
+            .verify_file(Arc::new(MemoryLoader::new()), "main.asm")
+            .unwrap_err();
+        assert!(matches!(error, InterpreterError::Load(..)), "{error}");
+    }
+    #[test]
+    fn test_verify_wrong_solution() {
+        let level = Level::from_toml(ADDER).unwrap();
+        let report = level
+            .verify("LOOP:\nIN 0, R0\nIN 1, R1\nADD R0, R0\nOUT A, 0\nJMP LOOP\n")
+            .unwrap();
+        assert!(!report.passed());
+        assert_eq!(report.score(), None);
+        assert_eq!(
+            report.tests[0].failures,
+            vec!["Output port 0: expected [4, 6], got [2, 4]".to_string()]
+        );
+    }
+    #[test]
+    fn test_verify_breaking_level_rules() {
+        let level = Level::from_toml(ADDER).unwrap();
+        let report = level
+            .verify("LOOP:\nIN 0, R0\nIN 1, R1\nMUL R0, 1\nADD A, R1\nOUT A, 0\nJMP LOOP\n")
+            .unwrap();
+        assert!(!report.passed());
+        assert!(report.tests.is_empty());
+        assert_eq!(
+            report.problems,
+            vec!["Line 4, column 1: MUL is not available in this level".to_string()]
+        );
+
+        let report = level
+            .verify("LOOP:\n  IN 0, R0\n  IN 1, R2\n  ADD R0, %R2\n  OUT A, 0\n  JMP LOOP\n")
+            .unwrap();
+        assert_eq!(
+            report.problems,
+            vec![
+                "Line 3, column 3: Register R2 is not available in this level".to_string(),
+                "Line 4, column 3: Register R2 is not available in this level".to_string(),
+            ]
+        );
+
+        let report = level
+            .verify("LOOP:\nIN 0, R0\nIN 1, R1\nADD A, R1\nADD R0, R1\nOUT A, 0\nJMP LOOP\n")
+            .unwrap();
+        assert_eq!(
+            report.problems,
+            vec!["The program has 6 instructions, but only 5 are allowed".to_string()]
+        );
+
+        let report = level.verify(format!("DATA %0, 1, 2\n{SOLUTION}")).unwrap();
+        assert_eq!(report.instructions, 6);
+        assert_eq!(
+            report.problems,
+            vec!["Line 1, column 1: DATA is not available in this level".to_string()]
+        );
+    }
+    #[test]
+    fn test_verify_runaway_program() {
+        let level = Level::from_toml(ADDER).unwrap();
+        let report = level.verify("LOOP:\nJMP LOOP\n").unwrap();
+        assert_eq!(
+            report.tests[0].failures[0],
+            "Did not finish within 100 cycles"
+        );
+    }
+    #[test]
+    fn test_expected_memory_and_console() {
+        let level = Level::from_toml(
+            r#"
+name = "Greeter"
+[[tests]]
+console_input = "Ada\n"
+memory = { 3 = 7 }
+expected = { console = "Ada\n", memory = { 3 = 8, 256 = 0xFF0000 } }
+"#,
+        )
+        .unwrap();
+        let report = level
+            .verify("DATA %3, 100\nREADLINE R0\nPRINTLN R0\nINC %3\nSET %256, 0xFF0000\n")
+            .unwrap();
+        assert!(report.passed(), "{report}");
+
+        let report = level.verify("READLINE R0\nPRINT R0\n").unwrap();
+        assert_eq!(
+            report.tests[0].failures,
+            vec![
+                "Memory %3: expected 8, got 7".to_string(),
+                "Memory %256: expected 16711680, got 0".to_string(),
+                "Console: expected \"Ada\\n\", got \"Ada\"".to_string(),
+            ]
+        );
+    }
+    #[test]
+    fn test_invalid_level() {
+        assert!(Level::from_toml("name = \"No tests\"").is_err());
+        assert!(Level::from_toml("name = \"x\"\ntests = []\nbogus = 1").is_err());
+    }
+    const RANDOM_ADDER: &str = r#"
+name = "Random adder"
+
+[random]
+count = 5
+reference = """
+LOOP:
+    IN 0, R0
+    IN 1, R1
+    ADD R0, R1
+    OUT A, 0
+    JMP LOOP
+"""
+inputs = [
+    { type = "number", port = 0, min = -100, max = 100, count = 3 },
+    { type = "number", port = 1, min = -100, max = 100, count = 3 },
+]
+"#;
+
+    #[test]
+    fn test_verify_random_tests() {
+        let level = Level::from_toml(RANDOM_ADDER).unwrap();
+        let report = level.verify(SOLUTION).unwrap();
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.tests.len(), 5);
+        assert!(report.tests.iter().all(|test| test.seed.is_some()));
+
+        let wrong = "LOOP:\nIN 0, R0\nIN 1, R1\nSUB R0, R1\nOUT A, 0\nJMP LOOP\n";
+        let report = level.verify(wrong).unwrap();
+        assert!(!report.passed());
+        let failed = &report.tests[0];
+        let seed = failed.seed.unwrap();
+        assert_eq!(failed.name, format!("Random (seed {seed})"));
+
+        let replayed = level.verify_with_seeds(wrong, &[seed]).unwrap();
+        assert_eq!(replayed.tests.len(), 1);
+        assert_eq!(replayed.tests[0].failures, failed.failures);
+    }
+    #[test]
+    fn test_random_test_is_reproducible() {
+        let level = Level::from_toml(RANDOM_ADDER).unwrap();
+        let test = level.random_test(1234).unwrap().unwrap();
+        let again = level.random_test(1234).unwrap().unwrap();
+        assert_eq!(test.inputs, again.inputs);
+        assert_eq!(test.inputs[&0].len(), 3);
+        let sums: Vec<Value> = test.inputs[&0]
+            .iter()
+            .zip(&test.inputs[&1])
+            .map(|(a, b)| a.add(b).unwrap())
+            .collect();
+        assert_eq!(test.expected.outputs[&0], sums);
+        assert!(test.expected.outputs[&1].is_empty());
+        assert!(
+            Level::from_toml(ADDER)
+                .unwrap()
+                .random_test(1)
+                .unwrap()
+                .is_none()
+        );
+    }
+    #[test]
+    fn test_generators() {
+        let mut rng = Rng::new(99);
+        let list = Generator::List {
+            port: 2,
+            min: 0,
+            max: 9,
+            min_length: 4,
+            max_length: 4,
+            sorted: true,
+            terminator: Some(Value::Number(-1)),
+        };
+        let (port, values) = list.generate(&mut rng);
+        assert_eq!(port, 2);
+        assert_eq!(values.len(), 5);
+        assert_eq!(values[4], Value::Number(-1));
+        assert!(values[..4].windows(2).all(|pair| {
+            pair[0]
+                .compare(&pair[1], &crate::ast::ComparisonOp::Le)
+                .unwrap()
+        }));
+
+        let strings = Generator::String {
+            port: 0,
+            alphabet: "ab".to_string(),
+            min_length: 1,
+            max_length: 3,
+            count: 10,
+        };
+        let (_, values) = strings.generate(&mut rng);
+        assert_eq!(values.len(), 10);
+        for value in values {
+            let Value::String(string) = value else {
+                panic!("Expected a string, got {value:?}");
+            };
+            assert!((1..=3).contains(&string.len()));
+            assert!(string.chars().all(|c| c == 'a' || c == 'b'));
+        }
+    }
+    #[test]
+    fn test_invalid_random_tests() {
+        let level = RANDOM_ADDER.replace(
+            "min = -100, max = 100, count = 3 },\n]",
+            "min = 5, max = 1 },\n]",
+        );
+        assert!(matches!(
+            Level::from_toml(&level),
+            Err(LevelError::Invalid(_))
+        ));
+        let level = RANDOM_ADDER.replace("count = 5\n", "count = 5\nbogus = 1\n");
+        assert!(Level::from_toml(&level).is_err());
+    }
+}
@@ -32,11 +32,25 @@ OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 #![deny(clippy::all)]
 pub use crate::value::Value;
 pub use interpreter::Interpreter;
+pub use memory_map::MemoryRegion;
+pub use restrictions::Restrictions;
+pub use source::{FileSystemLoader, MemoryLoader, SourceLoader};
 
+pub mod console;
+pub mod display;
 pub mod error;
 pub mod interpreter;
+pub mod level;
+pub mod memory_map;
+pub mod restrictions;
+pub mod rng;
+pub mod score;
+pub mod snapshot;
+pub mod source;
 pub mod value;
 pub use error::InterpreterError;
+pub use error::LevelError;
 pub use error::ValueError;
 mod ast;
 mod ast_builder;
+mod macros;
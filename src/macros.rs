@@ -0,0 +1,205 @@
+/*BSD 3-Clause License
+
+Copyright (c) 2025, Jeffrey Smith
+
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+
+1. Redistributions of source code must retain the above copyright notice, this
+   list of conditions and the following disclaimer.
+
+2. Redistributions in binary form must reproduce the above copyright notice,
+   this list of conditions and the following disclaimer in the documentation
+   and/or other materials provided with the distribution.
+
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+
+THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Macros defined with `MACRO name params ... ENDM`, which are expanded into their body wherever
+//! they're called
+
+use crate::error::Span;
+use std::collections::HashSet;
+
+/// A macro, as it was defined
+#[derive(Debug, Clone)]
+pub(crate) struct Macro {
+    pub(crate) name: String,
+    pub(crate) params: Vec<String>,
+    /// The source of the body, every line between `MACRO` and `ENDM`
+    pub(crate) body: String,
+    /// Where the `MACRO` line is
+    pub(crate) span: Span,
+    /// The line the body starts on
+    pub(crate) body_line: usize,
+}
+
+impl Macro {
+    /// The source of one expansion of the macro. Every parameter is replaced by its argument,
+    /// and every label and constant defined in the body gets `#id` added to its name, so each
+    /// expansion has its own that can't clash with the rest of the program or the arguments
+    pub(crate) fn expand(&self, args: &[&str], id: usize) -> String {
+        let labels = local_labels(&self.body);
+        let constants = local_constants(&self.body);
+        let chars: Vec<char> = self.body.chars().collect();
+        let mut expanded = String::with_capacity(self.body.len());
+        let mut i = 0;
+        while i < chars.len() {
+            let start = i;
+            let c = chars[i];
+            i += 1;
+            if c == ';' {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            } else if c == '"' || c == '\'' {
+                while i < chars.len() && chars[i] != c && chars[i] != '\n' {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                i = (i + 1).min(chars.len());
+            } else if c.is_ascii_digit() {
+                while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                while i < chars.len() && is_identifier_char(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let prefix = start.checked_sub(1).map(|before| chars[before]);
+                let is_constant = prefix == Some('.');
+                let is_local = prefix == Some('@');
+                if is_constant && constants.contains(&word)
+                    || is_local && labels.contains(&format!("@{word}"))
+                    || !is_constant && !is_local && labels.contains(&word)
+                {
+                    expanded.push_str(&word);
+                    expanded.push('#');
+                    expanded.push_str(&id.to_string());
+                    continue;
+                }
+                if let Some(position) = self.params.iter().position(|param| *param == word)
+                    && !is_constant
+                    && !is_local
+                {
+                    expanded.push_str(args[position]);
+                    continue;
+                }
+            }
+            expanded.extend(&chars[start..i]);
+        }
+        expanded
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '#')
+}
+
+/// The name at the start of some source, if there is one
+fn leading_identifier(source: &str) -> Option<&str> {
+    let end = source
+        .find(|c| !is_identifier_char(c))
+        .unwrap_or(source.len());
+    let name = &source[..end];
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        .then_some(name)
+}
+
+/// Every label defined in some source, with the `@` for local labels
+fn local_labels(source: &str) -> HashSet<String> {
+    source
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_start();
+            let (prefix, rest) = match line.strip_prefix('@') {
+                Some(rest) => ("@", rest),
+                None => ("", line),
+            };
+            let name = leading_identifier(rest)?;
+            rest[name.len()..]
+                .starts_with(':')
+                .then(|| format!("{prefix}{name}"))
+        })
+        .collect()
+}
+
+/// A line of source without the labels at its start, like `loop:` in `loop: DEFINE .a 1`
+fn skip_labels(line: &str) -> &str {
+    let mut line = line.trim_start();
+    loop {
+        let rest = line.strip_prefix(['@', '+', '-']).unwrap_or(line);
+        let name = leading_identifier(rest).unwrap_or_default();
+        match rest[name.len()..].strip_prefix(':') {
+            Some(after) if !name.is_empty() || rest.len() < line.len() => {
+                line = after.trim_start();
+            }
+            _ => return line,
+        }
+    }
+}
+
+/// Every constant defined in some source with `DEFINE` or `VAR`, without the `.`
+fn local_constants(source: &str) -> HashSet<String> {
+    source
+        .lines()
+        .filter_map(|line| {
+            let line = skip_labels(line);
+            let rest = ["define", "DEFINE", "var", "VAR"]
+                .iter()
+                .find_map(|keyword| line.strip_prefix(keyword))?;
+            let rest = rest.trim_start().strip_prefix('.')?;
+            leading_identifier(rest).map(ToString::to_string)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let swap = Macro {
+            name: "swap".to_string(),
+            params: vec!["a".to_string(), "b".to_string()],
+            body: "    define .tmp a\n\
+                   loop: MOV a, R7 ; a comment about a\n    \
+                   MOV b, a\n    \
+                   PRINT \"a b loop\"\n    \
+                   JMP loop .tmp=0x1a\n    \
+                   SET %b, .tmp\n\
+                   @a: JMP @a\n\
+                   x: DEFINE .k 1\n\
+                   SET R0, .k\n"
+                .to_string(),
+            span: Span::new(1, 1),
+            body_line: 2,
+        };
+        assert_eq!(
+            swap.expand(&["R1", ".tmp"], 3),
+            "    define .tmp#3 R1\n\
+             loop#3: MOV R1, R7 ; a comment about a\n    \
+             MOV .tmp, R1\n    \
+             PRINT \"a b loop\"\n    \
+             JMP loop#3 .tmp#3=0x1a\n    \
+             SET %.tmp, .tmp#3\n\
+             @a#3: JMP @a#3\n\
+             x#3: DEFINE .k#3 1\n\
+             SET R0, .k#3\n"
+        );
+    }
+}
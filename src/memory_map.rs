@@ -0,0 +1,158 @@
+/*BSD 3-Clause License
+
+Copyright (c) 2025, Jeffrey Smith
+
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+
+1. Redistributions of source code must retain the above copyright notice, this
+   list of conditions and the following disclaimer.
+
+2. Redistributions in binary form must reproduce the above copyright notice,
+   this list of conditions and the following disclaimer in the documentation
+   and/or other materials provided with the distribution.
+
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+
+THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+*/
+
+use crate::Value;
+use crate::error::InterpreterError;
+
+/// A block of addresses that is backed by something other than RAM, like the display
+pub trait MemoryRegion: Send + Sync {
+    /// How many addresses this region covers
+    fn size(&self) -> usize;
+    /// Read the value at `offset`, which is always less than `size()`
+    /// # Errors
+    /// Can return an error if the value can't be read
+    fn read(&self, offset: usize) -> Result<Value, InterpreterError>;
+    /// Write a value to `offset`, which is always less than `size()`
+    /// # Errors
+    /// Can return an error if the region doesn't accept the value
+    fn write(&self, offset: usize, value: Value) -> Result<(), InterpreterError>;
+}
+
+/// The regions mapped into the address space above RAM
+#[derive(Default)]
+pub struct MemoryMap {
+    regions: Vec<(usize, Box<dyn MemoryRegion>)>,
+}
+
+impl MemoryMap {
+    #[must_use]
+    pub fn new() -> MemoryMap {
+        MemoryMap::default()
+    }
+    pub(crate) fn with_region(start: usize, region: Box<dyn MemoryRegion>) -> MemoryMap {
+        MemoryMap {
+            regions: vec![(start, region)],
+        }
+    }
+    /// Map a region so that it starts at `start`
+    /// # Errors
+    /// Returns an error if the region overlaps with one that is already mapped
+    pub fn map(
+        &mut self,
+        start: usize,
+        region: Box<dyn MemoryRegion>,
+    ) -> Result<(), InterpreterError> {
+        let end = start.checked_add(region.size()).ok_or_else(|| {
+            InterpreterError::InvalidMemoryAddress(format!("Region at '{start}' is too large"))
+        })?;
+        if let Some((other, _)) = self
+            .regions
+            .iter()
+            .find(|(other, mapped)| start < other + mapped.size() && *other < end)
+        {
+            return Err(InterpreterError::InvalidMemoryAddress(format!(
+                "Region at '{start}' overlaps the region at '{other}'"
+            )));
+        }
+        self.regions.push((start, region));
+        Ok(())
+    }
+    /// Read from an address in one of the mapped regions
+    /// # Errors
+    /// Returns an error if nothing is mapped at the address, or if the region fails to read
+    pub fn read(&self, address: usize) -> Result<Value, InterpreterError> {
+        let (region, offset) = self.find(address)?;
+        region.read(offset)
+    }
+    /// Write to an address in one of the mapped regions
+    /// # Errors
+    /// Returns an error if nothing is mapped at the address, or if the region rejects the value
+    pub fn write(&self, address: usize, value: Value) -> Result<(), InterpreterError> {
+        let (region, offset) = self.find(address)?;
+        region.write(offset, value)
+    }
+
+    fn find(&self, address: usize) -> Result<(&dyn MemoryRegion, usize), InterpreterError> {
+        self.regions
+            .iter()
+            .find(|(start, region)| (*start..start + region.size()).contains(&address))
+            .map(|(start, region)| (region.as_ref(), address - start))
+            .ok_or_else(|| {
+                InterpreterError::InvalidMemoryAddress(format!("Address '{address}' out of range"))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::RwLock;
+
+    struct Cells(RwLock<Vec<Value>>);
+
+    impl MemoryRegion for Cells {
+        fn size(&self) -> usize {
+            self.0.read().unwrap().len()
+        }
+        fn read(&self, offset: usize) -> Result<Value, InterpreterError> {
+            Ok(self.0.read().unwrap()[offset].clone())
+        }
+        fn write(&self, offset: usize, value: Value) -> Result<(), InterpreterError> {
+            self.0.write().unwrap()[offset] = value;
+            Ok(())
+        }
+    }
+
+    fn cells(size: usize) -> Box<dyn MemoryRegion> {
+        Box::new(Cells(RwLock::new(vec![Value::default(); size])))
+    }
+
+    #[test]
+    fn test_map_dispatches_to_regions() {
+        let mut map = MemoryMap::new();
+        map.map(300, cells(4)).unwrap();
+        map.map(310, cells(2)).unwrap();
+
+        map.write(303, Value::Number(7)).unwrap();
+        map.write(310, Value::String("abc".to_string())).unwrap();
+        assert_eq!(map.read(303).unwrap(), Value::Number(7));
+        assert_eq!(map.read(310).unwrap(), Value::String("abc".to_string()));
+        assert!(map.read(304).is_err());
+        assert!(map.write(312, Value::Number(1)).is_err());
+    }
+    #[test]
+    fn test_overlapping_regions_are_rejected() {
+        let mut map = MemoryMap::new();
+        map.map(300, cells(10)).unwrap();
+        assert!(map.map(305, cells(10)).is_err());
+        assert!(map.map(295, cells(6)).is_err());
+        map.map(310, cells(1)).unwrap();
+    }
+}
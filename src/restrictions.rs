@@ -0,0 +1,154 @@
+/*BSD 3-Clause License
+
+Copyright (c) 2025, Jeffrey Smith
+
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+
+1. Redistributions of source code must retain the above copyright notice, this
+   list of conditions and the following disclaimer.
+
+2. Redistributions in binary form must reproduce the above copyright notice,
+   this list of conditions and the following disclaimer in the documentation
+   and/or other materials provided with the distribution.
+
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+
+THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Restrictions limit which instructions and registers a program may use, so that a level can
+//! take away `MUL` or leave the player with only `R0` and `R1`. They are checked when the program
+//! is parsed, before any of it runs.
+
+use crate::ast::{Instruction, Operand};
+use crate::error::{Diagnostic, Span};
+use std::collections::BTreeSet;
+
+/// Registers that can't be restricted, because instructions use them implicitly
+const ALWAYS_ALLOWED_REGISTERS: [&str; 3] = ["a", "f", "sp"];
+
+/// The instructions and registers a program may use. Everything is allowed by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Restrictions {
+    instructions: Option<BTreeSet<String>>,
+    registers: Option<BTreeSet<String>>,
+}
+
+impl Restrictions {
+    /// Restrictions that allow everything
+    #[must_use]
+    pub fn new() -> Restrictions {
+        Restrictions::default()
+    }
+    /// Only allow these instructions, given by their mnemonic like `"MUL"`
+    #[must_use]
+    pub fn allow_instructions<I, S>(mut self, instructions: I) -> Restrictions
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.instructions = Some(
+            instructions
+                .into_iter()
+                .map(|instruction| instruction.as_ref().to_uppercase())
+                .collect(),
+        );
+        self
+    }
+    /// Only allow these registers, like `"R0"`. `A`, `F` and `SP` are always allowed, since
+    /// instructions use them whether they're named or not
+    #[must_use]
+    pub fn allow_registers<I, S>(mut self, registers: I) -> Restrictions
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.registers = Some(
+            registers
+                .into_iter()
+                .map(|register| register.as_ref().to_lowercase())
+                .collect(),
+        );
+        self
+    }
+    /// Whether a program may use the instruction with this mnemonic
+    #[must_use]
+    pub fn allows_instruction(&self, mnemonic: &str) -> bool {
+        self.instructions
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(&mnemonic.to_uppercase()))
+    }
+    /// Whether a program may use this register
+    #[must_use]
+    pub fn allows_register(&self, register: &str) -> bool {
+        let register = register.to_lowercase();
+        ALWAYS_ALLOWED_REGISTERS.contains(&register.as_str())
+            || self
+                .registers
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(&register))
+    }
+    /// Find everything about `instruction` these restrictions don't allow
+    pub(crate) fn check(&self, instruction: &Instruction, span: &Span) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mnemonic = instruction.mnemonic();
+        if !self.allows_instruction(mnemonic) {
+            diagnostics.push(Diagnostic {
+                span: span.clone(),
+                message: format!("{mnemonic} is not available in this level"),
+            });
+        }
+        for operand in instruction.operands() {
+            if let Operand::Register(register) = operand
+                && !self.allows_register(register)
+            {
+                diagnostics.push(Diagnostic {
+                    span: span.clone(),
+                    message: format!(
+                        "Register {} is not available in this level",
+                        register.to_uppercase()
+                    ),
+                });
+            }
+        }
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_allows_everything() {
+        let restrictions = Restrictions::new();
+        assert!(restrictions.allows_instruction("MUL"));
+        assert!(restrictions.allows_register("R7"));
+    }
+    #[test]
+    fn test_restrictions_ignore_case() {
+        let restrictions = Restrictions::new()
+            .allow_instructions(["add", "Jmp"])
+            .allow_registers(["r0", "R1"]);
+        assert!(restrictions.allows_instruction("ADD"));
+        assert!(restrictions.allows_instruction("jmp"));
+        assert!(!restrictions.allows_instruction("MUL"));
+        assert!(restrictions.allows_register("R0"));
+        assert!(restrictions.allows_register("r1"));
+        assert!(!restrictions.allows_register("R2"));
+        assert!(restrictions.allows_register("A"));
+        assert!(restrictions.allows_register("sp"));
+    }
+}
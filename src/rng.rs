@@ -0,0 +1,118 @@
+/*BSD 3-Clause License
+
+Copyright (c) 2025, Jeffrey Smith
+
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+
+1. Redistributions of source code must retain the above copyright notice, this
+   list of conditions and the following disclaimer.
+
+2. Redistributions in binary form must reproduce the above copyright notice,
+   this list of conditions and the following disclaimer in the documentation
+   and/or other materials provided with the distribution.
+
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+
+THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! A small, fast pseudo-random number generator. It is not cryptographically secure, but the same
+//! seed always gives the same numbers on every platform, which is what replaying a test case or a
+//! snapshot needs.
+
+use std::hash::{BuildHasher, RandomState};
+
+/// A splitmix64 generator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// A generator that always gives the same numbers for the same `seed`
+    #[must_use]
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+    /// A seed that is different every time
+    #[must_use]
+    pub fn random_seed() -> u64 {
+        RandomState::new().hash_one(std::time::SystemTime::now())
+    }
+    /// The next 64 random bits
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    /// A random number from `low` to `high`, including both
+    /// # Panics
+    /// Panics if `low` is greater than `high`
+    pub fn range(&mut self, low: i64, high: i64) -> i64 {
+        assert!(low <= high, "Empty range {low}..={high}");
+        let span =
+            u128::try_from(i128::from(high) - i128::from(low) + 1).expect("The range isn't empty");
+        let offset = (u128::from(self.next_u64()) * span) >> 64;
+        let offset = i128::try_from(offset).expect("The offset is at most 64 bits");
+        i64::try_from(i128::from(low) + offset).expect("The result is between low and high")
+    }
+    /// A random index into something `len` long
+    /// # Panics
+    /// Panics if `len` is 0
+    pub fn index(&mut self, len: usize) -> usize {
+        assert!(len > 0, "Can't pick from nothing");
+        let len = u128::try_from(len).expect("usize fits in u128");
+        let index = (u128::from(self.next_u64()) * len) >> 64;
+        usize::try_from(index).expect("The index is less than len")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_numbers() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+    }
+    #[test]
+    fn test_range_is_inclusive() {
+        let mut rng = Rng::new(7);
+        let mut seen = [false; 5];
+        for _ in 0..1000 {
+            let n = rng.range(-2, 2);
+            assert!((-2..=2).contains(&n));
+            seen[usize::try_from(n + 2).unwrap()] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+        assert_eq!(rng.range(5, 5), 5);
+        let n = rng.range(i64::MIN, i64::MAX);
+        assert!((i64::MIN..=i64::MAX).contains(&n));
+    }
+    #[test]
+    fn test_index() {
+        let mut rng = Rng::new(3);
+        for _ in 0..100 {
+            assert!(rng.index(3) < 3);
+        }
+    }
+}
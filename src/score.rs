@@ -0,0 +1,133 @@
+/*BSD 3-Clause License
+
+Copyright (c) 2025, Jeffrey Smith
+
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+
+1. Redistributions of source code must retain the above copyright notice, this
+   list of conditions and the following disclaimer.
+
+2. Redistributions in binary form must reproduce the above copyright notice,
+   this list of conditions and the following disclaimer in the documentation
+   and/or other materials provided with the distribution.
+
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+
+THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+*/
+
+use crate::level::TestResult;
+use serde::Serialize;
+use std::fmt;
+
+/// How well a solution does on a level. Lower is better for every metric, and sorting scores
+/// orders them by cycles first, then by instructions, memory and stack
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct Score {
+    /// Instructions executed, added up over every test case
+    pub cycles: usize,
+    /// Instructions in the program
+    pub instructions: usize,
+    /// The most RAM slots written to in any one test case
+    pub memory: usize,
+    /// The deepest the stack got in any one test case
+    pub stack: usize,
+}
+
+impl Score {
+    /// The names of the metrics, in the same order as `values`
+    pub const METRICS: [&'static str; 4] = ["cycles", "instructions", "memory", "stack"];
+
+    /// Score a solution from the size of the program and the results of every test case
+    #[must_use]
+    pub fn new(instructions: usize, tests: &[TestResult]) -> Score {
+        Score {
+            cycles: tests.iter().map(|test| test.cycles).sum(),
+            instructions,
+            memory: tests.iter().map(|test| test.memory).max().unwrap_or(0),
+            stack: tests.iter().map(|test| test.stack).max().unwrap_or(0),
+        }
+    }
+    /// The value of every metric, in the same order as `METRICS`. Handy for putting scores into
+    /// histograms
+    #[must_use]
+    pub fn values(&self) -> [usize; 4] {
+        [self.cycles, self.instructions, self.memory, self.stack]
+    }
+    /// Whether this score is at least as good as `other` in every metric, and better in at
+    /// least one
+    #[must_use]
+    pub fn dominates(&self, other: &Score) -> bool {
+        let pairs = self.values().into_iter().zip(other.values());
+        pairs.clone().all(|(ours, theirs)| ours <= theirs)
+            && pairs.into_iter().any(|(ours, theirs)| ours < theirs)
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} cycles, {} instructions, {} memory, {} stack",
+            self.cycles, self.instructions, self.memory, self.stack
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(cycles: usize, memory: usize, stack: usize) -> TestResult {
+        TestResult {
+            name: String::new(),
+            cycles,
+            memory,
+            stack,
+            seed: None,
+            failures: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_score_aggregates_test_cases() {
+        let score = Score::new(7, &[result(10, 3, 0), result(25, 1, 4)]);
+        assert_eq!(
+            score,
+            Score {
+                cycles: 35,
+                instructions: 7,
+                memory: 3,
+                stack: 4
+            }
+        );
+        assert_eq!(score.values(), [35, 7, 3, 4]);
+        assert_eq!(
+            score.to_string(),
+            "35 cycles, 7 instructions, 3 memory, 4 stack"
+        );
+    }
+    #[test]
+    fn test_compare_scores() {
+        let fast = Score::new(10, &[result(5, 0, 0)]);
+        let small = Score::new(4, &[result(20, 0, 0)]);
+        let worse = Score::new(11, &[result(6, 0, 0)]);
+        assert!(fast < small);
+        assert!(!fast.dominates(&small));
+        assert!(!small.dominates(&fast));
+        assert!(fast.dominates(&worse));
+        assert!(!fast.dominates(&fast));
+    }
+}
@@ -0,0 +1,72 @@
+/*BSD 3-Clause License
+
+Copyright (c) 2025, Jeffrey Smith
+
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+
+1. Redistributions of source code must retain the above copyright notice, this
+   list of conditions and the following disclaimer.
+
+2. Redistributions in binary form must reproduce the above copyright notice,
+   this list of conditions and the following disclaimer in the documentation
+   and/or other materials provided with the distribution.
+
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+
+THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Snapshots save the state of a machine so it can be put back later, to replay a program from
+//! the middle or to undo a step while debugging.
+
+use crate::Value;
+use crate::rng::Rng;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Everything about a running machine other than its program, its console and any memory
+/// regions the host has mapped in. Take one with `Interpreter::snapshot` and put it back with
+/// `Interpreter::restore`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub(crate) registers: HashMap<String, Value>,
+    pub(crate) memory: Vec<Value>,
+    pub(crate) display: Vec<(i32, i32, i32)>,
+    pub(crate) stack: Vec<Value>,
+    pub(crate) call_stack: Vec<usize>,
+    pub(crate) inputs: Vec<VecDeque<Value>>,
+    pub(crate) outputs: Vec<VecDeque<Value>>,
+    pub(crate) pc: usize,
+    pub(crate) running: bool,
+    pub(crate) waiting: bool,
+    pub(crate) cycles: usize,
+    pub(crate) peak_stack_depth: usize,
+    pub(crate) memory_written: HashSet<usize>,
+    pub(crate) frames: usize,
+    pub(crate) frame_ready: bool,
+    pub(crate) rng: Rng,
+}
+
+impl Snapshot {
+    /// The program counter when the snapshot was taken
+    #[must_use]
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+    /// How many instructions had been executed when the snapshot was taken
+    #[must_use]
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+}
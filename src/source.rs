@@ -0,0 +1,151 @@
+/*BSD 3-Clause License
+
+Copyright (c) 2025, Jeffrey Smith
+
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+
+1. Redistributions of source code must retain the above copyright notice, this
+   list of conditions and the following disclaimer.
+
+2. Redistributions in binary form must reproduce the above copyright notice,
+   this list of conditions and the following disclaimer in the documentation
+   and/or other materials provided with the distribution.
+
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+
+THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Where the files a program includes with `INCLUDE "file"` come from. The command line reads
+//! them from the file system, and a game can keep them in memory instead.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+/// Loads the source of the files a program includes
+pub trait SourceLoader: Send + Sync {
+    /// Read the source of a file. `name` has already been resolved against the file that
+    /// included it, so it's relative to wherever the loader starts from
+    /// # Errors
+    /// Returns an error when the file doesn't exist or can't be read
+    fn load(&self, name: &str) -> io::Result<String>;
+}
+
+impl<L: SourceLoader + ?Sized> SourceLoader for Arc<L> {
+    fn load(&self, name: &str) -> io::Result<String> {
+        (**self).load(name)
+    }
+}
+
+/// Loads files from the file system, relative to the current directory
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSystemLoader;
+
+impl SourceLoader for FileSystemLoader {
+    fn load(&self, name: &str) -> io::Result<String> {
+        std::fs::read_to_string(name)
+    }
+}
+
+/// Loads files from a set kept in memory
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    files: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    #[must_use]
+    pub fn new() -> Self {
+        MemoryLoader::default()
+    }
+    /// Add a file, replacing any file that already has the same name
+    #[must_use]
+    pub fn file(mut self, name: impl Into<String>, source: impl Into<String>) -> Self {
+        self.files
+            .insert(resolve(None, &name.into()), source.into());
+        self
+    }
+}
+
+impl SourceLoader for MemoryLoader {
+    fn load(&self, name: &str) -> io::Result<String> {
+        self.files.get(name).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("There is no file called {name}"),
+            )
+        })
+    }
+}
+
+/// The name of the file `path` refers to when it's included from the file `from`. Relative paths
+/// start from the directory `from` is in, and `.` and `..` are worked out without looking at any
+/// files
+pub(crate) fn resolve(from: Option<&str>, path: &str) -> String {
+    let absolute = path.starts_with('/') || from.is_some_and(|from| from.starts_with('/'));
+    let mut parts: Vec<&str> = Vec::new();
+    if !path.starts_with('/')
+        && let Some(from) = from
+    {
+        parts.extend(from.split('/').filter(|part| !part.is_empty()));
+        parts.pop();
+    }
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.last().is_some_and(|last| *last != "..") => {
+                parts.pop();
+            }
+            ".." if absolute => {}
+            part => parts.push(part),
+        }
+    }
+    let resolved = parts.join("/");
+    if absolute {
+        format!("/{resolved}")
+    } else {
+        resolved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let cases = [
+            (None, "lib/math.asm", "lib/math.asm"),
+            (None, "./lib/../main.asm", "main.asm"),
+            (Some("main.asm"), "lib/math.asm", "lib/math.asm"),
+            (Some("lib/math.asm"), "util.asm", "lib/util.asm"),
+            (Some("lib/math.asm"), "../main.asm", "main.asm"),
+            (Some("main.asm"), "../shared/a.asm", "../shared/a.asm"),
+            (Some("/home/me/main.asm"), "lib/a.asm", "/home/me/lib/a.asm"),
+            (Some("lib/math.asm"), "/abs/a.asm", "/abs/a.asm"),
+        ];
+        for (from, path, expected) in cases {
+            assert_eq!(resolve(from, path), expected, "{from:?} {path}");
+        }
+    }
+
+    #[test]
+    fn test_memory_loader() {
+        let loader = MemoryLoader::new().file("./lib/math.asm", "INC R0\n");
+        assert_eq!(loader.load("lib/math.asm").unwrap(), "INC R0\n");
+        assert!(loader.load("missing.asm").is_err());
+    }
+}
@@ -30,9 +30,89 @@ OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crate::ast::ComparisonOp;
 use crate::error::ValueError;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// What `Value::type_code` reports for a Number
+pub const TYPE_NUMBER: i64 = 0;
+/// What `Value::type_code` reports for a String
+pub const TYPE_STRING: i64 = 1;
+
+/// The ways `Value::shift` can move bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShiftKind {
+    /// Shift left, filling with zeroes
+    Left,
+    /// Shift right, filling with copies of the sign bit
+    ArithmeticRight,
+    /// Shift right, filling with zeroes
+    LogicalRight,
+    /// Rotate left, so bits shifted out of the top come back in at the bottom
+    RotateLeft,
+    /// Rotate right, so bits shifted out of the bottom come back in at the top
+    RotateRight,
+}
+
+/// What happens when the result of arithmetic doesn't fit in a word
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ArithmeticMode {
+    /// Overflowing is an error
+    #[default]
+    Checked,
+    /// Results wrap around, so one more than the largest number is the smallest number
+    Wrapping,
+    /// Results stick at the largest or smallest number
+    Saturating,
+}
+
+/// How numbers behave in arithmetic: how wide they are, and what happens when a result doesn't
+/// fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Arithmetic {
+    pub mode: ArithmeticMode,
+    /// How many bits wide a number is, from 1 to 64
+    pub width: u32,
+}
+
+impl Default for Arithmetic {
+    fn default() -> Self {
+        Arithmetic {
+            mode: ArithmeticMode::Checked,
+            width: 64,
+        }
+    }
+}
+
+impl Arithmetic {
+    /// Fit the exact result of some arithmetic into a word. Gives back the number, and whether
+    /// it had to be wrapped or saturated to fit
+    /// # Errors
+    /// Returns an error when the result doesn't fit and the mode is `Checked`
+    pub fn fit(self, n: i128) -> Result<(i64, bool), ValueError> {
+        let width = self.width.clamp(1, 64);
+        let max = (1_i128 << (width - 1)) - 1;
+        let min = -max - 1;
+        let overflowed = !(min..=max).contains(&n);
+        let fitted = match self.mode {
+            _ if !overflowed => n,
+            ArithmeticMode::Checked => {
+                return Err(ValueError::Overflow(format!(
+                    "{n} doesn't fit in {width} bits"
+                )));
+            }
+            ArithmeticMode::Wrapping => {
+                let low = n.cast_unsigned() & u128::from(u64::MAX);
+                i128::from(from_bits(u64::try_from(low).unwrap_or_default(), width))
+            }
+            ArithmeticMode::Saturating => n.clamp(min, max),
+        };
+        let fitted = i64::try_from(fitted).map_err(|e| ValueError::Overflow(e.to_string()))?;
+        Ok((fitted, overflowed))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
 pub enum Value {
     Number(i64),
     String(String),
@@ -51,11 +131,26 @@ impl Value {
     }
     /// Add two `Value` together
     /// # Errors
-    /// Can return an error if you try to add a Number and a String
+    /// Can return an error if you try to add a Number and a String, or if the sum overflows
     pub fn add(&self, other: &Value) -> Result<Value, ValueError> {
+        self.add_with(other, Arithmetic::default())
+            .map(|(value, _)| value)
+    }
+    /// Add two `Value` together, following the rules of `arithmetic`. Also gives back whether the
+    /// sum overflowed
+    /// # Errors
+    /// Can return an error if you try to add a Number and a String, or if the sum overflows in
+    /// checked arithmetic
+    pub fn add_with(
+        &self,
+        other: &Value,
+        arithmetic: Arithmetic,
+    ) -> Result<(Value, bool), ValueError> {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
-            (Value::String(a), Value::String(b)) => Ok(Value::String(format!("{a}{b}"))),
+            (Value::Number(a), Value::Number(b)) => {
+                fit_number(arithmetic, i128::from(*a) + i128::from(*b))
+            }
+            (Value::String(a), Value::String(b)) => Ok((Value::String(format!("{a}{b}")), false)),
             _ => Err(ValueError::TypeMismatch(self.clone(), other.clone())),
         }
     }
@@ -63,10 +158,24 @@ impl Value {
     /// Negative numbers remove them from the end. This "kind of" works like Python's list indexing
     /// # Errors
     /// Can return an error when subtracting a number from a string, if the Number cannot be
-    /// converted
+    /// converted, or if the difference of two numbers overflows
     pub fn sub(&self, other: &Value) -> Result<Value, ValueError> {
+        self.sub_with(other, Arithmetic::default())
+            .map(|(value, _)| value)
+    }
+    /// Subtract two `Value`, following the rules of `arithmetic`. Also gives back whether the
+    /// difference overflowed
+    /// # Errors
+    /// The same as `sub`, except that overflowing is only an error in checked arithmetic
+    pub fn sub_with(
+        &self,
+        other: &Value,
+        arithmetic: Arithmetic,
+    ) -> Result<(Value, bool), ValueError> {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a - b)),
+            (Value::Number(a), Value::Number(b)) => {
+                fit_number(arithmetic, i128::from(*a) - i128::from(*b))
+            }
             (Value::String(a), Value::Number(b)) => {
                 let len = a.chars().count();
                 if *b >= 0 {
@@ -74,16 +183,19 @@ impl Value {
                         Ok(n) => n,
                         Err(e) => return Err(ValueError::ConversionError(format!("{e}"))),
                     };
-                    Ok(Value::String(a.chars().skip(skip_amount).collect()))
+                    Ok((Value::String(a.chars().skip(skip_amount).collect()), false))
                 } else {
                     let abs_len = match usize::try_from(b.unsigned_abs()) {
                         Ok(n) => n,
                         Err(e) => return Err(ValueError::ConversionError(format!("{e}"))),
                     };
                     if abs_len >= len {
-                        Ok(Value::String(String::new()))
+                        Ok((Value::String(String::new()), false))
                     } else {
-                        Ok(Value::String(a.chars().take(len - abs_len).collect()))
+                        Ok((
+                            Value::String(a.chars().take(len - abs_len).collect()),
+                            false,
+                        ))
                     }
                 }
             }
@@ -96,16 +208,31 @@ impl Value {
     /// the number of times it's being multiplied by.
     /// If you multiply it by number less than or equal to zero, you will get an empty string
     /// # Errors
-    /// Returns an error if you try to multiply two strings together
+    /// Returns an error if you try to multiply two strings together, or if the product of two
+    /// numbers overflows
     pub fn mul(&self, other: &Value) -> Result<Value, ValueError> {
+        self.mul_with(other, Arithmetic::default())
+            .map(|(value, _)| value)
+    }
+    /// Multiply two `Value`, following the rules of `arithmetic`. Also gives back whether the
+    /// product overflowed
+    /// # Errors
+    /// The same as `mul`, except that overflowing is only an error in checked arithmetic
+    pub fn mul_with(
+        &self,
+        other: &Value,
+        arithmetic: Arithmetic,
+    ) -> Result<(Value, bool), ValueError> {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
+            (Value::Number(a), Value::Number(b)) => {
+                fit_number(arithmetic, i128::from(*a) * i128::from(*b))
+            }
             (Value::String(a), Value::Number(b)) | (Value::Number(b), Value::String(a)) => {
                 let mut new_string = String::new();
                 for _ in 0..*b {
                     new_string += a;
                 }
-                Ok(Value::String(new_string))
+                Ok((Value::String(new_string), false))
             }
             _ => Err(ValueError::InvalidOperation(
                 "Invalid multiplication between two strings".to_string(),
@@ -113,25 +240,35 @@ impl Value {
         }
     }
     /// Dividing two strings gives us the size difference between the two strings. Division between
-    /// two numbers is always truncated since it will always be integer division
+    /// two numbers is always truncated towards zero since it will always be integer division, so
+    /// `-7 / 2` is -3
     /// # Errors
-    /// Returns an error if you try to divide by zero, or if you try to divide a String by a
-    /// number. Can also return an error if trying to get the length of either String in a
-    /// String/String div fails
+    /// Returns an error if you try to divide by zero, if the quotient overflows, or if you try to
+    /// divide a String by a number. Can also return an error if trying to get the length of
+    /// either String in a String/String div fails
     pub fn div(&self, other: &Value) -> Result<Value, ValueError> {
+        self.div_with(other, Arithmetic::default())
+            .map(|(value, _)| value)
+    }
+    /// Divide two `Value`, following the rules of `arithmetic`. Also gives back whether the
+    /// quotient overflowed, which only happens when dividing the smallest number by -1
+    /// # Errors
+    /// The same as `div`, except that overflowing is only an error in checked arithmetic
+    pub fn div_with(
+        &self,
+        other: &Value,
+        arithmetic: Arithmetic,
+    ) -> Result<(Value, bool), ValueError> {
         match (self, other) {
+            (Value::Number(a), Value::Number(0)) => Err(ValueError::DivisionByZero(*a, 0)),
             (Value::Number(a), Value::Number(b)) => {
-                if *b > 0 {
-                    Ok(Value::Number(a / b))
-                } else {
-                    Err(ValueError::DivisionByZero(*a, *b))
-                }
+                fit_number(arithmetic, i128::from(*a) / i128::from(*b))
             }
             (Value::String(a), Value::String(b)) => {
                 let len_left = i64::try_from(a.chars().count());
                 let len_right = i64::try_from(b.chars().count());
                 match (len_left, len_right) {
-                    (Ok(left), Ok(right)) => Ok(Value::Number(left - right)),
+                    (Ok(left), Ok(right)) => Ok((Value::Number(left - right), false)),
                     (Ok(_), Err(e)) => Err(ValueError::ConversionError(format!(
                         "Right value in div not valid: {e}"
                     ))),
@@ -148,6 +285,407 @@ impl Value {
             )),
         }
     }
+    /// Divide two numbers, rounding the quotient down towards negative infinity rather than
+    /// towards zero, so `-7 FDIV 2` is -4. Follows the rules of `arithmetic`, and also gives back
+    /// whether the quotient overflowed
+    /// # Errors
+    /// Returns an error when dividing by zero, when either Value is a String, or when the
+    /// quotient overflows in checked arithmetic
+    pub fn floor_div_with(
+        &self,
+        other: &Value,
+        arithmetic: Arithmetic,
+    ) -> Result<(Value, bool), ValueError> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(0)) => Err(ValueError::DivisionByZero(*a, 0)),
+            (Value::Number(a), Value::Number(b)) => {
+                let (a, b) = (i128::from(*a), i128::from(*b));
+                let quotient = a / b;
+                let rounded_up = a % b != 0 && (a < 0) != (b < 0);
+                fit_number(arithmetic, quotient - i128::from(rounded_up))
+            }
+            _ => Err(ValueError::TypeMismatch(self.clone(), other.clone())),
+        }
+    }
+    /// The Euclidean remainder of dividing two numbers. Unlike `%` in most languages, the result
+    /// is never negative: it is always from 0 up to, but not including, the size of the divisor.
+    /// `-7 MOD 3` is 2, not -1
+    /// # Errors
+    /// Returns an error when dividing by zero, or when either Value is a String
+    pub fn rem(&self, other: &Value) -> Result<Value, ValueError> {
+        self.rem_with(other, Arithmetic::default())
+            .map(|(value, _)| value)
+    }
+    /// The Euclidean remainder of dividing two numbers, following the rules of `arithmetic`.
+    /// Also gives back whether the remainder overflowed, which it can't for numbers that fit in
+    /// a word
+    /// # Errors
+    /// The same as `rem`
+    pub fn rem_with(
+        &self,
+        other: &Value,
+        arithmetic: Arithmetic,
+    ) -> Result<(Value, bool), ValueError> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(0)) => Err(ValueError::DivisionByZero(*a, 0)),
+            (Value::Number(a), Value::Number(b)) => {
+                fit_number(arithmetic, i128::from(*a).rem_euclid(i128::from(*b)))
+            }
+            _ => Err(ValueError::TypeMismatch(self.clone(), other.clone())),
+        }
+    }
+    /// Truncated division that gives both the quotient and the remainder. The quotient is rounded
+    /// towards zero and the remainder has the same sign as the dividend, so `-7 DIVMOD 3` is -2
+    /// with a remainder of -1. `quotient * other + remainder` is always `self`
+    /// # Errors
+    /// Returns an error when dividing by zero, when the quotient doesn't fit, or when either
+    /// Value is a String
+    pub fn divmod(&self, other: &Value) -> Result<(Value, Value), ValueError> {
+        self.divmod_with(other, Arithmetic::default())
+            .map(|(quotient, remainder, _)| (quotient, remainder))
+    }
+    /// Truncated division that gives both the quotient and the remainder, following the rules of
+    /// `arithmetic`. Also gives back whether the quotient overflowed, which only happens when
+    /// dividing the smallest number by -1
+    /// # Errors
+    /// The same as `divmod`, except that overflowing is only an error in checked arithmetic
+    pub fn divmod_with(
+        &self,
+        other: &Value,
+        arithmetic: Arithmetic,
+    ) -> Result<(Value, Value, bool), ValueError> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(0)) => Err(ValueError::DivisionByZero(*a, 0)),
+            (Value::Number(a), Value::Number(b)) => {
+                let (a, b) = (i128::from(*a), i128::from(*b));
+                let (quotient, overflowed) = fit_number(arithmetic, a / b)?;
+                let (remainder, _) = fit_number(arithmetic, a % b)?;
+                Ok((quotient, remainder, overflowed))
+            }
+            _ => Err(ValueError::TypeMismatch(self.clone(), other.clone())),
+        }
+    }
+    /// Flip the sign of a number
+    /// # Errors
+    /// Returns an error when a String is supplied, or for the smallest number, which has no
+    /// positive counterpart
+    pub fn neg(&self) -> Result<Value, ValueError> {
+        self.neg_with(Arithmetic::default()).map(|(value, _)| value)
+    }
+    /// Flip the sign of a number, following the rules of `arithmetic`. Also gives back whether
+    /// the result overflowed
+    /// # Errors
+    /// Returns an error when a String is supplied, or when the result overflows in checked
+    /// arithmetic
+    pub fn neg_with(&self, arithmetic: Arithmetic) -> Result<(Value, bool), ValueError> {
+        match self {
+            Value::Number(a) => fit_number(arithmetic, -i128::from(*a)),
+            Value::String(_) => Err(ValueError::TypeMismatch(self.clone(), Value::Number(0))),
+        }
+    }
+    /// The absolute value of a number
+    /// # Errors
+    /// Returns an error when a String is supplied, or for the smallest number, which has no
+    /// positive counterpart
+    pub fn abs(&self) -> Result<Value, ValueError> {
+        self.abs_with(Arithmetic::default()).map(|(value, _)| value)
+    }
+    /// The absolute value of a number, following the rules of `arithmetic`. Also gives back
+    /// whether the result overflowed
+    /// # Errors
+    /// Returns an error when a String is supplied, or when the result overflows in checked
+    /// arithmetic
+    pub fn abs_with(&self, arithmetic: Arithmetic) -> Result<(Value, bool), ValueError> {
+        match self {
+            Value::Number(a) => fit_number(arithmetic, i128::from(*a).abs()),
+            Value::String(_) => Err(ValueError::TypeMismatch(self.clone(), Value::Number(0))),
+        }
+    }
+    /// The smaller of two Values. Strings are compared alphabetically
+    /// # Errors
+    /// Returns an error when comparing a Number and a String
+    pub fn min(&self, other: &Value) -> Result<Value, ValueError> {
+        if self.compare(other, &ComparisonOp::Le)? {
+            Ok(self.clone())
+        } else {
+            Ok(other.clone())
+        }
+    }
+    /// The larger of two Values. Strings are compared alphabetically
+    /// # Errors
+    /// Returns an error when comparing a Number and a String
+    pub fn max(&self, other: &Value) -> Result<Value, ValueError> {
+        if self.compare(other, &ComparisonOp::Ge)? {
+            Ok(self.clone())
+        } else {
+            Ok(other.clone())
+        }
+    }
+    /// Shift or rotate the bits of a number by `amount`, treating it as a two's complement number
+    /// `width` bits wide. Gives back the result, sign extended from `width` bits, and the last bit
+    /// that was shifted or rotated out, which is the carry. Shifting by 0 never carries
+    /// # Errors
+    /// Returns an error when either Value is a String, when `amount` is negative, or when
+    /// `width` isn't from 1 to 64
+    pub fn shift(
+        &self,
+        amount: &Value,
+        kind: ShiftKind,
+        width: u32,
+    ) -> Result<(Value, bool), ValueError> {
+        let (Value::Number(n), Value::Number(amount)) = (self, amount) else {
+            return Err(ValueError::TypeMismatch(self.clone(), amount.clone()));
+        };
+        if !(1..=64).contains(&width) {
+            return Err(ValueError::InvalidOperation(format!(
+                "Invalid word width {width}"
+            )));
+        }
+        let amount = u32::try_from(*amount)
+            .map_err(|_| ValueError::InvalidOperation(format!("Invalid shift amount {amount}")))?;
+        let bits = to_bits(*n, width);
+        let bit = |bits: u64, index: u32| (bits >> index) & 1 == 1;
+        let (result, carry) = match kind {
+            _ if amount == 0 => (bits, false),
+            ShiftKind::Left if amount <= width => (
+                bits.checked_shl(amount).unwrap_or(0) & mask(width),
+                bit(bits, width - amount),
+            ),
+            ShiftKind::LogicalRight if amount <= width => {
+                (bits.checked_shr(amount).unwrap_or(0), bit(bits, amount - 1))
+            }
+            ShiftKind::Left | ShiftKind::LogicalRight => (0, false),
+            ShiftKind::ArithmeticRight => (
+                to_bits(from_bits(bits, width) >> amount.min(63), width),
+                bit(bits, (amount - 1).min(width - 1)),
+            ),
+            ShiftKind::RotateLeft | ShiftKind::RotateRight => {
+                let amount = if kind == ShiftKind::RotateLeft {
+                    amount % width
+                } else {
+                    (width - amount % width) % width
+                };
+                let result = (bits.checked_shl(amount).unwrap_or(0)
+                    | bits.checked_shr(width - amount).unwrap_or(0))
+                    & mask(width);
+                if kind == ShiftKind::RotateLeft {
+                    (result, bit(result, 0))
+                } else {
+                    (result, bit(result, width - 1))
+                }
+            }
+        };
+        Ok((Value::Number(from_bits(result, width)), carry))
+    }
+    /// How many characters are in a string
+    /// # Errors
+    /// Returns an error when a Number is supplied
+    pub fn len(&self) -> Result<Value, ValueError> {
+        let text = self.as_text()?;
+        i64::try_from(text.chars().count())
+            .map(Value::Number)
+            .map_err(|e| ValueError::ConversionError(format!("{e}")))
+    }
+    /// The character at `index` as a string. Like `sub`, negative indexes count from the end, so
+    /// -1 is the last character
+    /// # Errors
+    /// Returns an error when the index is past either end of the string, or the Values are the
+    /// wrong types
+    pub fn char_at(&self, index: &Value) -> Result<Value, ValueError> {
+        let text = self.as_text()?;
+        let Value::Number(index) = index else {
+            return Err(ValueError::TypeMismatch(self.clone(), index.clone()));
+        };
+        let len = text.chars().count();
+        resolve_index(*index, len)
+            .filter(|position| *position < len)
+            .and_then(|position| text.chars().nth(position))
+            .map(|c| Value::String(c.to_string()))
+            .ok_or_else(|| out_of_range(*index, len))
+    }
+    /// Up to `length` characters starting at `start`. A negative `start` counts from the end, and
+    /// the result stops early if the string runs out
+    /// # Errors
+    /// Returns an error when `start` is past either end of the string, `length` is negative, or
+    /// the Values are the wrong types
+    pub fn substr(&self, start: &Value, length: &Value) -> Result<Value, ValueError> {
+        let text = self.as_text()?;
+        let (Value::Number(start), Value::Number(length)) = (start, length) else {
+            let wrong = if let Value::Number(_) = start {
+                length
+            } else {
+                start
+            };
+            return Err(ValueError::TypeMismatch(self.clone(), wrong.clone()));
+        };
+        let len = text.chars().count();
+        let position = resolve_index(*start, len).ok_or_else(|| out_of_range(*start, len))?;
+        let length = usize::try_from(*length)
+            .map_err(|_| ValueError::InvalidOperation(format!("Negative length {length}")))?;
+        Ok(Value::String(
+            text.chars().skip(position).take(length).collect(),
+        ))
+    }
+    /// The index of the first character of the first place `needle` appears in the string, or -1
+    /// if it doesn't
+    /// # Errors
+    /// Returns an error when either Value is a Number
+    pub fn find(&self, needle: &Value) -> Result<Value, ValueError> {
+        let text = self.as_text()?;
+        let needle = needle.as_text()?;
+        match text.find(needle) {
+            Some(byte) => i64::try_from(text[..byte].chars().count())
+                .map(Value::Number)
+                .map_err(|e| ValueError::ConversionError(format!("{e}"))),
+            None => Ok(Value::Number(-1)),
+        }
+    }
+    /// The string in upper case
+    /// # Errors
+    /// Returns an error when a Number is supplied
+    pub fn upper(&self) -> Result<Value, ValueError> {
+        Ok(Value::String(self.as_text()?.to_uppercase()))
+    }
+    /// The string in lower case
+    /// # Errors
+    /// Returns an error when a Number is supplied
+    pub fn lower(&self) -> Result<Value, ValueError> {
+        Ok(Value::String(self.as_text()?.to_lowercase()))
+    }
+    /// The string without whitespace at either end
+    /// # Errors
+    /// Returns an error when a Number is supplied
+    pub fn trim(&self) -> Result<Value, ValueError> {
+        Ok(Value::String(self.as_text()?.trim().to_string()))
+    }
+    /// The characters of the string in reverse order
+    /// # Errors
+    /// Returns an error when a Number is supplied
+    pub fn reverse(&self) -> Result<Value, ValueError> {
+        Ok(Value::String(self.as_text()?.chars().rev().collect()))
+    }
+    /// The pieces of the string between each `separator`. An empty separator splits the string
+    /// into its characters
+    /// # Errors
+    /// Returns an error when either Value is a Number
+    pub fn split(&self, separator: &Value) -> Result<Vec<Value>, ValueError> {
+        let text = self.as_text()?;
+        let separator = separator.as_text()?;
+        if separator.is_empty() {
+            return Ok(text.chars().map(|c| Value::String(c.to_string())).collect());
+        }
+        Ok(text
+            .split(separator)
+            .map(|piece| Value::String(piece.to_string()))
+            .collect())
+    }
+    /// A number written out as a string in `radix`, which can be anywhere from 2 to 36. Digits
+    /// past 9 are lower case letters, and there is no `0x` or `0b` prefix
+    /// # Errors
+    /// Returns an error when a String is supplied or the radix is out of range
+    pub fn itoa(&self, radix: u32) -> Result<Value, ValueError> {
+        let Value::Number(n) = self else {
+            return Err(ValueError::TypeMismatch(self.clone(), Value::Number(0)));
+        };
+        check_radix(radix)?;
+        let mut magnitude = n.unsigned_abs();
+        let mut digits = Vec::new();
+        loop {
+            let digit = u32::try_from(magnitude % u64::from(radix))
+                .map_err(|e| ValueError::ConversionError(format!("{e}")))?;
+            digits.extend(char::from_digit(digit, radix));
+            magnitude /= u64::from(radix);
+            if magnitude == 0 {
+                break;
+            }
+        }
+        if *n < 0 {
+            digits.push('-');
+        }
+        Ok(Value::String(digits.iter().rev().collect()))
+    }
+    /// Read a number out of a string. Without a radix it is read the same way as a number in a
+    /// program, so `0x` and `0b` prefixes are hex and binary. With one, the matching prefix is
+    /// still allowed for 16 and 2. Whitespace at either end is ignored
+    /// # Errors
+    /// Returns an error when a Number is supplied, the radix is out of range, or the string isn't
+    /// a number that fits in 64 bits
+    pub fn atoi(&self, radix: Option<u32>) -> Result<Value, ValueError> {
+        let text = self.as_text()?;
+        let invalid = || {
+            ValueError::InvalidOperation(format!(
+                "'{text}' is not a number in base {}",
+                radix.unwrap_or(10)
+            ))
+        };
+        let trimmed = text.trim();
+        let (negative, unsigned) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed),
+        };
+        let lower = unsigned.to_lowercase();
+        let (radix, digits) = match (radix, lower.strip_prefix("0x"), lower.strip_prefix("0b")) {
+            (None | Some(16), Some(hex), _) => (16, hex),
+            (None | Some(2), _, Some(binary)) => (2, binary),
+            (radix, _, _) => (radix.unwrap_or(10), lower.as_str()),
+        };
+        check_radix(radix)?;
+        if digits.starts_with(['+', '-']) {
+            return Err(invalid());
+        }
+        let magnitude = u64::from_str_radix(digits, radix).map_err(|_| invalid())?;
+        let n = if negative {
+            -i128::from(magnitude)
+        } else {
+            i128::from(magnitude)
+        };
+        i64::try_from(n)
+            .map(Value::Number)
+            .map_err(|_| ValueError::Overflow(format!("'{text}' doesn't fit in 64 bits")))
+    }
+    /// The code point of a string that is exactly one character long
+    /// # Errors
+    /// Returns an error when a Number is supplied, or the string isn't one character
+    pub fn ord(&self) -> Result<Value, ValueError> {
+        let text = self.as_text()?;
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(Value::Number(i64::from(u32::from(c)))),
+            _ => Err(ValueError::InvalidOperation(format!(
+                "'{text}' is not a single character"
+            ))),
+        }
+    }
+    /// The one character string with the code point in a number
+    /// # Errors
+    /// Returns an error when a String is supplied, or the number isn't a valid code point
+    pub fn chr(&self) -> Result<Value, ValueError> {
+        let Value::Number(n) = self else {
+            return Err(ValueError::TypeMismatch(self.clone(), Value::Number(0)));
+        };
+        u32::try_from(*n)
+            .ok()
+            .and_then(char::from_u32)
+            .map(|c| Value::String(c.to_string()))
+            .ok_or_else(|| ValueError::InvalidOperation(format!("{n} is not a valid character")))
+    }
+    /// Which type of Value this is, as `TYPE_NUMBER` or `TYPE_STRING`
+    #[must_use]
+    pub fn type_code(&self) -> Value {
+        match self {
+            Value::Number(_) => Value::Number(TYPE_NUMBER),
+            Value::String(_) => Value::Number(TYPE_STRING),
+        }
+    }
+    fn as_text(&self) -> Result<&str, ValueError> {
+        match self {
+            Value::String(text) => Ok(text),
+            Value::Number(_) => Err(ValueError::TypeMismatch(
+                self.clone(),
+                Value::String(String::new()),
+            )),
+        }
+    }
     /// Logical AND on two numbers.
     /// # Errors
     /// Returns an error when String Values are supplied
@@ -191,6 +729,54 @@ impl Value {
         }
     }
 }
+/// A mask of the lowest `width` bits
+fn mask(width: u32) -> u64 {
+    u64::MAX >> (64 - width)
+}
+
+/// The lowest `width` bits of a number
+fn to_bits(n: i64, width: u32) -> u64 {
+    n.cast_unsigned() & mask(width)
+}
+
+/// Sign extend a number that is `width` bits wide
+fn from_bits(bits: u64, width: u32) -> i64 {
+    (bits << (64 - width)).cast_signed() >> (64 - width)
+}
+
+/// Turn an index that may count from the end into one that counts from the start. Indexes up to
+/// and including `len` are allowed
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let position = usize::try_from(index.unsigned_abs()).ok()?;
+    if index >= 0 {
+        Some(position).filter(|position| *position <= len)
+    } else {
+        len.checked_sub(position)
+    }
+}
+
+fn check_radix(radix: u32) -> Result<(), ValueError> {
+    if (2..=36).contains(&radix) {
+        Ok(())
+    } else {
+        Err(ValueError::InvalidOperation(format!(
+            "Radix {radix} is not between 2 and 36"
+        )))
+    }
+}
+
+fn out_of_range(index: i64, len: usize) -> ValueError {
+    ValueError::InvalidOperation(format!(
+        "Index {index} is out of range for a string of {len} characters"
+    ))
+}
+
+fn fit_number(arithmetic: Arithmetic, n: i128) -> Result<(Value, bool), ValueError> {
+    arithmetic
+        .fit(n)
+        .map(|(n, overflowed)| (Value::Number(n), overflowed))
+}
+
 impl Default for Value {
     fn default() -> Self {
         Value::Number(0)